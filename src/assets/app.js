@@ -0,0 +1,84 @@
+"use strict";
+
+/** The URL of the multipart stream of `name`, with optional query parameters. */
+function streamUrl(name, params) {
+    const url = new URL(`/streams/${encodeURIComponent(name)}/image.jpeg`, location.href);
+    for (const [key, value] of Object.entries(params)) {
+        if (value !== undefined && value !== "") {
+            url.searchParams.set(key, value);
+        }
+    }
+    return url;
+}
+
+/** Shows the stream `name` in `img`, and reconnects if the stream breaks. */
+function play(img, name) {
+    const params = {
+        profile: img.dataset.profile,
+        fps: img.dataset.fps,
+    };
+    let delay = 1000;
+    const connect = () => {
+        const url = streamUrl(name, params);
+        // a new URL, so that the browser does not reuse the broken response
+        url.searchParams.set("_", Date.now());
+        img.src = url;
+    };
+    img.addEventListener("load", () => {
+        img.classList.remove("error");
+        delay = 1000;
+    });
+    img.addEventListener("error", () => {
+        img.classList.add("error");
+        setTimeout(connect, delay);
+        delay = Math.min(2 * delay, 30000);
+    });
+    connect();
+}
+
+async function streamNames() {
+    const response = await fetch("/api/streams");
+    if (!response.ok) {
+        throw new Error(`${response.status} ${response.statusText}`);
+    }
+    return (await response.json()).map((stream) => stream.name);
+}
+
+/** Instantiates `template` for every stream and appends the copies to `parent`. */
+function fill(parent, template, names) {
+    for (const name of names) {
+        const node = template.content.firstElementChild.cloneNode(true);
+        node.href = `/view/${encodeURIComponent(name)}`;
+        node.title = name;
+        node.querySelector(".name").textContent = name;
+        parent.append(node);
+        play(node.querySelector("img"), name);
+    }
+}
+
+async function main() {
+    const body = document.body;
+    if (body.classList.contains("view")) {
+        const name = decodeURIComponent(location.pathname.split("/").pop());
+        document.title = `${name} – mjpeg-restream`;
+        play(document.getElementById("view"), name);
+    } else if (body.classList.contains("index")) {
+        const names = await streamNames();
+        fill(document.getElementById("streams"), document.getElementById("thumbnail"), names);
+    } else if (body.classList.contains("wall")) {
+        const names = await streamNames();
+        const params = new URLSearchParams(location.search);
+        const wall = document.getElementById("wall");
+        const columns = Number(params.get("columns")) || Math.ceil(Math.sqrt(names.length));
+        const rows = Math.max(1, Math.ceil(names.length / columns));
+        wall.style.setProperty("--columns", columns);
+        wall.style.setProperty("--rows", rows);
+        const tile = document.getElementById("tile");
+        tile.content.querySelector("img").dataset.fps = params.get("fps") ?? "";
+        fill(wall, tile, names);
+    }
+}
+
+main().catch((err) => {
+    document.body.append(`Could not load the streams: ${err}`);
+});
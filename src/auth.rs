@@ -0,0 +1,772 @@
+//! Optional authentication of the clients, and per-stream access lists.
+//!
+//! Authentication is enabled as soon as any user, token or signing key is configured. Users and
+//! tokens share one namespace, so the `allow` list of a source can name either of them.
+//!
+//! URLs signed with the signing key (`?exp=…&sig=…`) grant access to a single stream until they
+//! expire, regardless of its access list. The expiry is only checked when the request starts, so
+//! streams that were opened in time are not cut off.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use actix_web::dev::Payload;
+use actix_web::http::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use base64::prelude::{Engine, BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use futures_util::future::{ready, Ready};
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::Url;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::source::{self, Source};
+
+/// Users and tokens that may access the streams.
+#[derive(Debug, Default)]
+pub struct Auth {
+    /// Password hashes by user name
+    users: HashMap<String, PasswordHash>,
+    /// Token names by the SHA-256 of the token
+    tokens: HashMap<[u8; 32], String>,
+    /// SHA-256 of `user:password` pairs that were verified recently, so that bcrypt does not run
+    /// for every request, with the time they were verified
+    verified: Mutex<HashMap<[u8; 32], Instant>>,
+    /// Key of the signed URLs
+    signing_key: Option<SigningKey>,
+}
+
+/// Secret used to sign and verify URLs with HMAC-SHA256.
+#[derive(Debug)]
+struct SigningKey(Vec<u8>);
+
+impl SigningKey {
+    /// Reads the key, trailing whitespace is ignored. Blocks.
+    fn read(path: &Path) -> Result<Self, Error> {
+        let mut key = fs::read(path).map_err(|err| Error::Read(path.to_owned(), err))?;
+        while key.last().is_some_and(u8::is_ascii_whitespace) {
+            let _ = key.pop();
+        }
+        if key.len() < MIN_KEY_LEN {
+            return Err(Error::ShortKey(path.to_owned()));
+        }
+        Ok(Self(key))
+    }
+
+    /// Authenticates the stream `name` and the expiry `exp` in seconds since the epoch.
+    fn mac(&self, name: &str, exp: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).unwrap();
+        mac.update(format!("{exp}:{name}").as_bytes());
+        mac
+    }
+
+    fn sign(&self, name: &str, exp: u64) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(self.mac(name, exp).finalize().into_bytes())
+    }
+
+    fn verify(&self, name: &str, exp: u64, sig: &str) -> bool {
+        let now = crate::millis_since_epoch(SystemTime::now()) / 1000;
+        let Ok(sig) = BASE64_URL_SAFE_NO_PAD.decode(sig) else {
+            return false;
+        };
+        exp >= now && self.mac(name, exp).verify_slice(&sig).is_ok()
+    }
+}
+
+#[derive(Debug)]
+enum PasswordHash {
+    /// `$2y$…`, as written by `htpasswd -B`
+    Bcrypt(String),
+    /// `{SHA}…`, as written by `htpasswd -s`
+    Sha1([u8; 20]),
+}
+
+impl PasswordHash {
+    fn parse(hash: &str) -> Result<Self, String> {
+        if let Some(digest) = hash.strip_prefix("{SHA}") {
+            let digest = BASE64_STANDARD
+                .decode(digest)
+                .ok()
+                .and_then(|digest| digest.try_into().ok())
+                .ok_or_else(|| "Malformed SHA-1 hash".to_owned())?;
+            Ok(Self::Sha1(digest))
+        } else if ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+        {
+            Ok(Self::Bcrypt(hash.to_owned()))
+        } else if hash.starts_with("$apr1$") {
+            Err(
+                "MD5 hashes are not supported, use `htpasswd -B` to create a bcrypt hash"
+                    .to_owned(),
+            )
+        } else {
+            Err(
+                "Only bcrypt (`htpasswd -B`) and SHA-1 (`htpasswd -s`) hashes are supported"
+                    .to_owned(),
+            )
+        }
+    }
+
+    fn is_bcrypt(&self) -> bool {
+        matches!(self, Self::Bcrypt(_))
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        match self {
+            Self::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            Self::Sha1(digest) => Sha1::digest(password)[..].ct_eq(&digest[..]).into(),
+        }
+    }
+}
+
+impl Auth {
+    /// Reads the configured files. Blocks.
+    pub fn load(args: &Args) -> Result<Self, Error> {
+        let mut auth = Self::default();
+        if let Some(path) = &args.htpasswd {
+            for (line, entry) in read_lines(path)? {
+                let invalid = |msg: String| Error::Invalid(path.clone(), line, msg);
+                let (user, hash) = entry
+                    .split_once(':')
+                    .ok_or_else(|| invalid("Expected USER:HASH".to_owned()))?;
+                let hash = PasswordHash::parse(hash).map_err(invalid)?;
+                let _ = auth.users.insert(user.to_owned(), hash);
+            }
+        }
+        for token in &args.tokens {
+            auth.add_token(token).map_err(Error::Token)?;
+        }
+        for path in &args.token_files {
+            for (line, entry) in read_lines(path)? {
+                auth.add_token(&entry)
+                    .map_err(|msg| Error::Invalid(path.clone(), line, msg))?;
+            }
+        }
+        if let Some(path) = &args.signing_key_file {
+            auth.signing_key = Some(SigningKey::read(path)?);
+        }
+        Ok(auth)
+    }
+
+    fn add_token(&mut self, entry: &str) -> Result<(), String> {
+        match entry.split_once(':') {
+            Some((name, token)) if !name.is_empty() && !token.is_empty() => {
+                let _ = self
+                    .tokens
+                    .insert(Sha256::digest(token).into(), name.to_owned());
+                Ok(())
+            },
+            _ => Err("Expected NAME:TOKEN".to_owned()),
+        }
+    }
+
+    /// Whether any user, token or signing key was configured.
+    pub fn enabled(&self) -> bool {
+        !self.users.is_empty() || !self.tokens.is_empty() || self.signing_key.is_some()
+    }
+
+    /// The name of the user or token the client sent, `None` if it sent no credentials.
+    fn authenticate(
+        &self,
+        req: &HttpRequest,
+        query: &CredentialsQuery,
+    ) -> Result<Option<String>, Denied> {
+        if !self.enabled() {
+            return Ok(None);
+        }
+        if let Some(value) = req.headers().get(AUTHORIZATION) {
+            return self
+                .check_header(value)
+                .map(Some)
+                .ok_or(Denied::Unauthorized);
+        }
+        // clients like `<img>` cannot send headers, RFC 6750, 2.3
+        match &query.access_token {
+            Some(token) => self
+                .check_token(token)
+                .map(Some)
+                .ok_or(Denied::Unauthorized),
+            None => Ok(None),
+        }
+    }
+
+    fn check_header(&self, value: &HeaderValue) -> Option<String> {
+        let (scheme, credentials) = value.to_str().ok()?.split_once(' ')?;
+        let credentials = credentials.trim();
+        if scheme.eq_ignore_ascii_case("basic") {
+            let credentials = BASE64_STANDARD.decode(credentials).ok()?;
+            let credentials = String::from_utf8(credentials).ok()?;
+            let (user, password) = credentials.split_once(':')?;
+            self.check_password(user, password).then(|| user.to_owned())
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            self.check_token(credentials)
+        } else {
+            None
+        }
+    }
+
+    fn check_password(&self, user: &str, password: &str) -> bool {
+        let Some(hash) = self.users.get(user) else {
+            // take as long as for a known user, so that user names cannot be told by the timing
+            if let Some(hash) = self.users.values().find(|hash| hash.is_bcrypt()) {
+                let _ = hash.verify(password);
+            }
+            return false;
+        };
+        let key = Sha256::digest(format!("{user}:{password}")).into();
+        let now = Instant::now();
+        {
+            let mut verified = self.verified.lock().unwrap();
+            match verified.get(&key) {
+                Some(&time) if now.duration_since(time) < VERIFIED_TTL => return true,
+                Some(_) => {
+                    let _ = verified.remove(&key);
+                },
+                None => {},
+            }
+        }
+        let valid = hash.verify(password);
+        if valid {
+            let mut verified = self.verified.lock().unwrap();
+            if verified.len() >= MAX_VERIFIED {
+                verified.retain(|_, time| now.duration_since(*time) < VERIFIED_TTL);
+            }
+            if verified.len() >= MAX_VERIFIED {
+                let oldest = verified.iter().min_by_key(|(_, &time)| time);
+                if let Some((&oldest, _)) = oldest {
+                    let _ = verified.remove(&oldest);
+                }
+            }
+            let _ = verified.insert(key, now);
+        }
+        valid
+    }
+
+    fn check_token(&self, token: &str) -> Option<String> {
+        let key: [u8; 32] = Sha256::digest(token).into();
+        self.tokens.get(&key).cloned()
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct CredentialsQuery {
+    /// Bearer token
+    access_token: Option<String>,
+    /// Expiry of a signed URL, in seconds since the epoch
+    exp: Option<u64>,
+    /// Signature of a signed URL
+    sig: Option<String>,
+}
+
+/// The client of a request, see [`Auth`].
+#[derive(Debug)]
+pub struct User {
+    auth: web::Data<Auth>,
+    /// Name of the user or token, `None` for anonymous clients
+    name: Option<String>,
+    /// `exp` and `sig` of a signed URL
+    signature: Option<(u64, String)>,
+}
+
+impl User {
+    pub fn extract(req: &HttpRequest) -> Result<Self, Denied> {
+        let auth = req
+            .app_data::<web::Data<Auth>>()
+            .expect("Auth was not registered")
+            .clone();
+        let query = web::Query::<CredentialsQuery>::from_query(req.query_string())
+            .map_or_else(|_| CredentialsQuery::default(), web::Query::into_inner);
+        let name = auth.authenticate(req, &query)?;
+        let signature = query.exp.zip(query.sig);
+        Ok(Self {
+            auth,
+            name,
+            signature,
+        })
+    }
+
+    /// Fails for anonymous clients if authentication is enabled.
+    pub fn require(&self) -> Result<(), Denied> {
+        match self.name {
+            None if self.auth.enabled() => Err(Denied::Unauthorized),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the client may view the source, according to its `public` and `allow` settings, or
+    /// a signed URL.
+    pub fn authorize(&self, source: &Source) -> Result<(), Denied> {
+        if !self.auth.enabled() || source.public {
+            return Ok(());
+        }
+        if let Some((exp, sig)) = &self.signature {
+            return match &self.auth.signing_key {
+                Some(key) if key.verify(&source.name, *exp, sig) => Ok(()),
+                _ => Err(Denied::Signature),
+            };
+        }
+        match (&self.name, &source.allow) {
+            (None, _) => Err(Denied::Unauthorized),
+            (Some(_), None) => Ok(()),
+            (Some(name), Some(allow)) if allow.contains(name) => Ok(()),
+            (Some(_), Some(_)) => Err(Denied::Forbidden),
+        }
+    }
+}
+
+impl FromRequest for User {
+    type Error = Denied;
+    type Future = Ready<Result<Self, Denied>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Self::extract(req))
+    }
+}
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+pub enum Denied {
+    #[error("Authentication required")]
+    Unauthorized,
+    #[error("Access denied")]
+    Forbidden,
+    #[error("The signature is invalid or expired")]
+    Signature,
+}
+
+impl ResponseError for Denied {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Denied::Unauthorized => StatusCode::UNAUTHORIZED,
+            Denied::Forbidden | Denied::Signature => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let challenges: &[&str] = match self {
+            Denied::Unauthorized => &[
+                r#"Basic realm="mjpeg-restream", charset="UTF-8""#,
+                r#"Bearer realm="mjpeg-restream""#,
+            ],
+            Denied::Forbidden | Denied::Signature => &[],
+        };
+        challenges
+            .iter()
+            .fold(
+                &mut HttpResponse::build(self.status_code()),
+                |resp, &challenge| resp.append_header((WWW_AUTHENTICATE, challenge)),
+            )
+            .content_type(mime::TEXT_PLAIN_UTF_8)
+            .body(format!("{self}\n"))
+    }
+}
+
+/// Fails if a source has an access list, but authentication is not enabled on any listener.
+///
+/// A listener without authentication, e.g. a Unix socket of a local recorder, is deliberate if
+/// others require authentication.
+pub fn check_access_lists(auths: &[web::Data<Auth>]) -> Result<(), Error> {
+    if auths.iter().any(|auth| auth.enabled()) {
+        return Ok(());
+    }
+    match source::sources().iter().find(|s| s.allow.is_some()) {
+        Some(source) => Err(Error::Unprotected(source.name.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Non-empty lines that are not comments, with their line numbers.
+fn read_lines(path: &Path) -> Result<Vec<(usize, String)>, Error> {
+    let content = fs::read_to_string(path).map_err(|err| Error::Read(path.to_owned(), err))?;
+    Ok(content
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(idx, line)| (idx, line.to_owned()))
+        .collect())
+}
+
+/// Authentication settings, given on the command line or for a listener in the configuration
+/// file. If a listener has no settings, the ones of the command line are used.
+#[derive(clap::Args, serde::Deserialize, Debug, Clone, Default)]
+#[group(id = "auth")]
+#[serde(default, deny_unknown_fields)]
+pub struct Args {
+    /// htpasswd file with the users that may view the streams; only bcrypt (`htpasswd -B`) and
+    /// SHA-1 (`htpasswd -s`) hashes are supported, not the MD5 hashes that `htpasswd` creates by
+    /// default
+    #[arg(long, value_name = "PATH")]
+    htpasswd: Option<PathBuf>,
+    /// Bearer token that may view the streams, can be given multiple times
+    #[arg(long = "token", value_name = "NAME:TOKEN")]
+    tokens: Vec<String>,
+    /// File with one bearer token per line, as `NAME:TOKEN`
+    #[arg(long = "token-file", value_name = "PATH")]
+    token_files: Vec<PathBuf>,
+    /// File with the secret key of signed URLs, see the `sign` subcommand
+    #[arg(long, value_name = "PATH")]
+    signing_key_file: Option<PathBuf>,
+}
+
+/// Prints a URL of a stream that is valid for a limited time.
+#[derive(clap::Args, Debug)]
+pub struct SignArgs {
+    /// Name of the stream
+    stream: String,
+    /// File with the secret key, as given to `--signing-key-file`
+    #[arg(long, value_name = "PATH")]
+    signing_key_file: PathBuf,
+    /// Number of seconds the URL is valid
+    #[arg(long, default_value_t = 3600)]
+    valid_for: u64,
+    /// Endpoint of the stream, e.g. `snapshot.jpeg` or `ws`
+    #[arg(long, default_value = "image.jpeg")]
+    endpoint: String,
+    /// Address of the server; only the path and query are printed if it is omitted
+    #[arg(long, value_name = "URL")]
+    base_url: Option<Url>,
+}
+
+/// Prints a signed URL. Blocks.
+pub fn sign(args: SignArgs) -> Result<(), Error> {
+    let key = SigningKey::read(&args.signing_key_file)?;
+    let exp = SystemTime::now() + Duration::from_secs(args.valid_for);
+    let exp = crate::millis_since_epoch(exp) / 1000;
+
+    let relative = args.base_url.is_none();
+    let mut url = match args.base_url {
+        Some(url) => url,
+        None => Url::parse("http://localhost/").unwrap(),
+    };
+    let _ = url
+        .path_segments_mut()
+        .map_err(|()| Error::BaseUrl)?
+        .pop_if_empty()
+        .extend(["streams", &args.stream, &args.endpoint]);
+    let _ = url
+        .query_pairs_mut()
+        .append_pair("exp", &exp.to_string())
+        .append_pair("sig", &key.sign(&args.stream, exp));
+
+    if relative {
+        println!("{}?{}", url.path(), url.query().unwrap_or_default());
+    } else {
+        println!("{url}");
+    }
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not read {0:?}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Line {1} of {0:?} is invalid: {2}")]
+    Invalid(PathBuf, usize, String),
+    #[error("Token is invalid: {0}")]
+    Token(String),
+    #[error("Source {0:?} has an access list, but authentication is not configured")]
+    Unprotected(String),
+    #[error("The signing key in {0:?} must be at least {MIN_KEY_LEN} bytes long")]
+    ShortKey(PathBuf),
+    #[error("The base URL must be hierarchical, e.g. `https://example.com/`")]
+    BaseUrl,
+}
+
+/// Minimum length of the signing key in bytes.
+const MIN_KEY_LEN: usize = 16;
+
+/// How long a verified password is remembered.
+const VERIFIED_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Maximum number of remembered passwords, the oldest is forgotten first.
+const MAX_VERIFIED: usize = 1024;
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    /// `htpasswd -s` of the password `password`
+    const SHA1_HASH: &str = "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=";
+
+    fn auth() -> Auth {
+        let mut auth = Auth::default();
+        let bcrypt = bcrypt::hash("hunter2", 4).unwrap();
+        let _ = auth
+            .users
+            .insert("alice".to_owned(), PasswordHash::parse(&bcrypt).unwrap());
+        let _ = auth
+            .users
+            .insert("bob".to_owned(), PasswordHash::parse(SHA1_HASH).unwrap());
+        auth.add_token("recorder:s3cr3t").unwrap();
+        auth.signing_key = Some(SigningKey(b"0123456789abcdef".to_vec()));
+        auth
+    }
+
+    fn header(value: &str) -> HeaderValue {
+        HeaderValue::from_str(value).unwrap()
+    }
+
+    fn basic(user: &str, password: &str) -> HeaderValue {
+        header(&format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{user}:{password}"))
+        ))
+    }
+
+    fn source(config: &str) -> Source {
+        let config = format!("name = \"cam\"\nurl = \"http://localhost/\"\n{config}");
+        Source::new(toml::from_str(&config).unwrap()).unwrap()
+    }
+
+    fn user(name: Option<&str>, signature: Option<(u64, String)>) -> User {
+        User {
+            auth: web::Data::new(auth()),
+            name: name.map(str::to_owned),
+            signature,
+        }
+    }
+
+    fn now() -> u64 {
+        crate::millis_since_epoch(SystemTime::now()) / 1000
+    }
+
+    #[test]
+    fn parse_hashes() {
+        assert!(matches!(
+            PasswordHash::parse("$2y$05$abcdefghijklmnopqrstuu"),
+            Ok(PasswordHash::Bcrypt(_)),
+        ));
+        assert!(matches!(
+            PasswordHash::parse(SHA1_HASH),
+            Ok(PasswordHash::Sha1(_)),
+        ));
+        assert!(PasswordHash::parse("{SHA}not base64!").is_err());
+        assert!(PasswordHash::parse("{SHA}AAAA").is_err());
+        assert!(PasswordHash::parse("$apr1$salt$hash")
+            .is_err_and(|msg| msg.contains("MD5") && msg.contains("htpasswd -B")));
+        assert!(PasswordHash::parse("plain").is_err());
+    }
+
+    #[test]
+    fn verify_passwords() {
+        let bcrypt = PasswordHash::parse(&bcrypt::hash("hunter2", 4).unwrap()).unwrap();
+        assert!(bcrypt.verify("hunter2"));
+        assert!(!bcrypt.verify("hunter3"));
+
+        let sha1 = PasswordHash::parse(SHA1_HASH).unwrap();
+        assert!(sha1.verify("password"));
+        assert!(!sha1.verify("Password"));
+        assert!(!sha1.verify(""));
+    }
+
+    #[test]
+    fn basic_auth() {
+        let auth = auth();
+        assert_eq!(
+            auth.check_header(&basic("alice", "hunter2")).as_deref(),
+            Some("alice"),
+        );
+        // the second check uses the cache
+        assert_eq!(
+            auth.check_header(&basic("alice", "hunter2")).as_deref(),
+            Some("alice"),
+        );
+        assert_eq!(
+            auth.check_header(&basic("bob", "password")).as_deref(),
+            Some("bob"),
+        );
+        assert_eq!(auth.check_header(&basic("alice", "password")), None);
+        assert_eq!(auth.check_header(&basic("carol", "hunter2")), None);
+        assert_eq!(auth.check_header(&header("Basic !!!")), None);
+        assert_eq!(
+            auth.check_header(&header(&format!(
+                "Basic {}",
+                BASE64_STANDARD.encode("alice")
+            ))),
+            None,
+        );
+    }
+
+    #[test]
+    fn verified_cache() {
+        let auth = auth();
+        assert!(!auth.check_password("carol", "hunter2"));
+        assert!(auth.verified.lock().unwrap().is_empty());
+
+        // a cached pair is trusted until it expires, even if the password changed meanwhile
+        let key: [u8; 32] = Sha256::digest("alice:hunter3").into();
+        let _ = auth.verified.lock().unwrap().insert(key, Instant::now());
+        assert!(auth.check_password("alice", "hunter3"));
+        let expired = Instant::now() - VERIFIED_TTL;
+        let _ = auth.verified.lock().unwrap().insert(key, expired);
+        assert!(!auth.check_password("alice", "hunter3"));
+        assert!(!auth.verified.lock().unwrap().contains_key(&key));
+
+        // the oldest entry makes room
+        let oldest = [0; 32];
+        {
+            let mut verified = auth.verified.lock().unwrap();
+            let now = Instant::now();
+            for i in 0..MAX_VERIFIED {
+                let mut key = [0; 32];
+                key[..8].copy_from_slice(&(i as u64).to_le_bytes());
+                let _ = verified.insert(key, now - Duration::from_secs(u64::from(key == oldest)));
+            }
+        }
+        assert!(auth.check_password("bob", "password"));
+        let verified = auth.verified.lock().unwrap();
+        assert_eq!(verified.len(), MAX_VERIFIED);
+        assert!(!verified.contains_key(&oldest));
+        assert!(verified.contains_key(&<[u8; 32]>::from(Sha256::digest("bob:password"))));
+    }
+
+    #[test]
+    fn bearer_auth() {
+        let auth = auth();
+        assert_eq!(
+            auth.check_header(&header("Bearer s3cr3t")).as_deref(),
+            Some("recorder"),
+        );
+        assert_eq!(
+            auth.check_header(&header("bearer  s3cr3t ")).as_deref(),
+            Some("recorder"),
+        );
+        assert_eq!(auth.check_header(&header("Bearer recorder")), None);
+        assert_eq!(auth.check_header(&header("Digest s3cr3t")), None);
+        assert_eq!(auth.check_header(&header("s3cr3t")), None);
+        assert!(Auth::default().add_token("recorder").is_err());
+        assert!(Auth::default().add_token(":token").is_err());
+    }
+
+    #[test]
+    fn authenticate_requests() {
+        let auth = auth();
+        let query = CredentialsQuery::default();
+        let req = TestRequest::default().to_http_request();
+        assert!(matches!(auth.authenticate(&req, &query), Ok(None)));
+
+        let req = TestRequest::default()
+            .insert_header((AUTHORIZATION, "Bearer s3cr3t"))
+            .to_http_request();
+        assert_eq!(
+            auth.authenticate(&req, &query).unwrap().as_deref(),
+            Some("recorder"),
+        );
+
+        // invalid credentials are not treated like anonymous clients
+        let req = TestRequest::default()
+            .insert_header((AUTHORIZATION, "Bearer wrong"))
+            .to_http_request();
+        assert!(matches!(
+            auth.authenticate(&req, &query),
+            Err(Denied::Unauthorized),
+        ));
+
+        let req = TestRequest::default().to_http_request();
+        let query = CredentialsQuery {
+            access_token: Some("s3cr3t".to_owned()),
+            ..CredentialsQuery::default()
+        };
+        assert_eq!(
+            auth.authenticate(&req, &query).unwrap().as_deref(),
+            Some("recorder"),
+        );
+
+        let auth = Auth::default();
+        let req = TestRequest::default()
+            .insert_header((AUTHORIZATION, "Bearer wrong"))
+            .to_http_request();
+        assert!(matches!(auth.authenticate(&req, &query), Ok(None)));
+    }
+
+    #[test]
+    fn access_lists() {
+        let open = source("");
+        let public = source("public = true\nallow = [\"alice\"]");
+        let restricted = source("allow = [\"alice\", \"recorder\"]");
+
+        assert!(user(None, None).authorize(&public).is_ok());
+        assert!(matches!(
+            user(None, None).authorize(&open),
+            Err(Denied::Unauthorized),
+        ));
+        assert!(user(Some("bob"), None).authorize(&open).is_ok());
+        assert!(user(Some("alice"), None).authorize(&restricted).is_ok());
+        assert!(user(Some("recorder"), None).authorize(&restricted).is_ok());
+        assert!(matches!(
+            user(Some("bob"), None).authorize(&restricted),
+            Err(Denied::Forbidden),
+        ));
+        assert!(user(None, None).require().is_err());
+        assert!(user(Some("bob"), None).require().is_ok());
+
+        let disabled = User {
+            auth: web::Data::new(Auth::default()),
+            name: None,
+            signature: None,
+        };
+        assert!(disabled.require().is_ok());
+        assert!(disabled.authorize(&restricted).is_ok());
+    }
+
+    #[test]
+    fn signed_urls() {
+        let key = SigningKey(b"0123456789abcdef".to_vec());
+        let exp = now() + 60;
+        let sig = key.sign("cam", exp);
+        assert!(key.verify("cam", exp, &sig));
+        assert!(!key.verify("other", exp, &sig));
+        assert!(!key.verify("cam", exp + 1, &sig));
+        assert!(!key.verify("cam", exp, "not base64!"));
+        assert!(!key.verify("cam", exp, &sig[1..]));
+
+        let other = SigningKey(b"fedcba9876543210".to_vec());
+        assert!(!other.verify("cam", exp, &sig));
+
+        let expired = now() - 1;
+        assert!(!key.verify("cam", expired, &key.sign("cam", expired)));
+    }
+
+    #[test]
+    fn signed_url_access() {
+        let restricted = source("allow = [\"alice\"]");
+        let key = SigningKey(b"0123456789abcdef".to_vec());
+        let exp = now() + 60;
+
+        let signature = Some((exp, key.sign("cam", exp)));
+        assert!(user(None, signature).authorize(&restricted).is_ok());
+
+        let signature = Some((exp, key.sign("other", exp)));
+        assert!(matches!(
+            user(None, signature).authorize(&restricted),
+            Err(Denied::Signature),
+        ));
+
+        // an invalid signature is not ignored, even for an authorized user
+        let signature = Some((exp - 120, key.sign("cam", exp - 120)));
+        assert!(matches!(
+            user(Some("alice"), signature).authorize(&restricted),
+            Err(Denied::Signature),
+        ));
+
+        let mut auth = auth();
+        auth.signing_key = None;
+        let user = User {
+            auth: web::Data::new(auth),
+            name: None,
+            signature: Some((exp, key.sign("cam", exp))),
+        };
+        assert!(matches!(
+            user.authorize(&restricted),
+            Err(Denied::Signature)
+        ));
+    }
+}
@@ -0,0 +1,42 @@
+use std::path::{Path, PathBuf};
+
+use crate::sender::ListenerConfig;
+use crate::source::SourceConfig;
+
+/// The content of the file given with `--config`.
+///
+/// ```toml
+/// [[source]]
+/// name = "garden"
+/// url = "http://camera.local/mjpeg"
+/// filters = [
+///     { type = "rotate", degrees = 90 },
+///     { type = "overlay", format = "%Y-%m-%d %H:%M:%S" },
+/// ]
+/// ```
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, rename = "source")]
+    pub sources: Vec<SourceConfig>,
+    /// Additional listeners, see [`ListenerConfig`]
+    #[serde(default, rename = "listener")]
+    pub listeners: Vec<ListenerConfig>,
+}
+
+impl Config {
+    /// Reads and parses the configuration file. Blocks.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| Error::Read(path.to_owned(), err))?;
+        toml::from_str(&content).map_err(|err| Error::Parse(path.to_owned(), err))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not read configuration file {0:?}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Could not parse configuration file {0:?}")]
+    Parse(PathBuf, #[source] toml::de::Error),
+}
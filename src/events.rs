@@ -0,0 +1,46 @@
+//! Notifications about a source, sent to clients as server-sent events.
+
+use bytes::Bytes;
+
+use crate::listener::ErrorCategory;
+use crate::source::State;
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    /// The state of the connection to the upstream changed
+    State { state: State },
+    /// The connection to the upstream was lost
+    Error { category: ErrorCategory },
+    /// A new image is available
+    Frame {
+        sequence: u64,
+        size: usize,
+        /// Milliseconds since the epoch when the image was received
+        received: u64,
+        /// The source is offline, the image is a placeholder
+        placeholder: bool,
+    },
+    /// The size of the images changed
+    Resolution { width: u16, height: u16 },
+    /// The number of clients that stream images changed
+    Clients { count: usize },
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::State { .. } => "state",
+            Event::Error { .. } => "error",
+            Event::Frame { .. } => "frame",
+            Event::Resolution { .. } => "resolution",
+            Event::Clients { .. } => "clients",
+        }
+    }
+
+    /// The event in the `text/event-stream` format.
+    pub fn to_sse(&self) -> Bytes {
+        let data = serde_json::to_string(self).unwrap();
+        format!("event: {}\ndata: {data}\n\n", self.name()).into()
+    }
+}
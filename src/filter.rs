@@ -0,0 +1,377 @@
+//! Processing steps between the multipart parser and the [`UpdateStream`].
+//!
+//! [`UpdateStream`]: crate::update_stream::UpdateStream
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime};
+
+use anyhow::Context;
+use bytes::Bytes;
+use http::HeaderMap;
+use image::codecs::jpeg::JpegEncoder;
+use image::{ImageFormat, RgbImage};
+use prometheus::{Histogram, IntCounter};
+
+use crate::jpeg::Info;
+use crate::mask::Mask;
+use crate::metrics::metrics;
+use crate::overlay::Overlay;
+use crate::script::Script;
+use crate::transform::{Flip, Rect, Rotation};
+use crate::validate::Validate;
+use crate::watermark::Watermark;
+
+/// A processing step for received images.
+///
+/// Filters run on a blocking thread, so they may take their time.
+pub trait FrameFilter: Send + Sync + fmt::Debug {
+    /// Short name of the filter, used in the statistics.
+    fn name(&self) -> &'static str;
+
+    fn apply(&self, frame: &mut FilterFrame) -> anyhow::Result<Verdict>;
+}
+
+/// What to do with a frame after a [`FrameFilter`] was applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Hand the frame to the next filter.
+    Keep,
+    /// Discard the frame, the remaining filters are skipped.
+    Drop,
+}
+
+/// An image that is processed by a [`Chain`].
+///
+/// The JPEG image is only decoded if a filter asks for the pixels, and only re-encoded if it was
+/// decoded.
+#[derive(Debug)]
+pub struct FilterFrame {
+    /// The headers of the multipart part
+    pub headers: HeaderMap,
+    /// When the frame was received
+    pub received: SystemTime,
+    /// Additional headers to send along with the image
+    pub tags: HeaderMap,
+    /// Name of the stream to publish the frame to, instead of the stream of the [`Chain`]
+    pub route: Option<String>,
+    jpeg: Option<Bytes>,
+    image: Option<RgbImage>,
+}
+
+impl FilterFrame {
+    pub fn new(headers: HeaderMap, body: Bytes, received: SystemTime) -> Self {
+        Self {
+            headers,
+            received,
+            tags: HeaderMap::new(),
+            route: None,
+            jpeg: Some(body),
+            image: None,
+        }
+    }
+
+    /// The decoded image, which may be modified in-place.
+    pub fn image(&mut self) -> anyhow::Result<&mut RgbImage> {
+        let image = match (self.image.take(), self.jpeg.take()) {
+            (Some(image), _) => image,
+            (None, Some(jpeg)) => image::load_from_memory_with_format(&jpeg, ImageFormat::Jpeg)
+                .context("Could not decode image")?
+                .into_rgb8(),
+            (None, None) => unreachable!(),
+        };
+        Ok(self.image.insert(image))
+    }
+
+    /// Width and height of the image, `None` if the JPEG image is malformed.
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        match (&self.image, &self.jpeg) {
+            (Some(image), _) => Some(image.dimensions()),
+            (None, Some(jpeg)) => {
+                Info::parse(jpeg).map(|info| (u32::from(info.width), u32::from(info.height)))
+            },
+            (None, None) => unreachable!(),
+        }
+    }
+
+    /// The JPEG image, if it was not decoded yet.
+    pub fn jpeg(&self) -> Option<&Bytes> {
+        self.jpeg.as_ref()
+    }
+
+    /// Replaces the image, e.g. with a rotated copy.
+    pub fn set_image(&mut self, image: RgbImage) {
+        self.jpeg = None;
+        self.image = Some(image);
+    }
+
+    /// Replaces the image with a JPEG image, e.g. with a losslessly rotated copy.
+    pub fn set_jpeg(&mut self, jpeg: Bytes) {
+        self.jpeg = Some(jpeg);
+        self.image = None;
+    }
+
+    fn into_jpeg(
+        jpeg: Option<Bytes>,
+        image: Option<RgbImage>,
+        quality: u8,
+    ) -> anyhow::Result<Bytes> {
+        match (jpeg, image) {
+            (Some(jpeg), _) => Ok(jpeg),
+            (None, Some(image)) => {
+                let mut data = Vec::new();
+                JpegEncoder::new_with_quality(&mut data, quality)
+                    .encode_image(&image)
+                    .context("Could not encode image")?;
+                Ok(data.into())
+            },
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+/// The result of a [`Chain`].
+#[derive(Debug)]
+pub struct Output {
+    /// The headers of the multipart part, as modified by the filters
+    pub headers: HeaderMap,
+    pub jpeg: Bytes,
+    pub tags: HeaderMap,
+    pub route: Option<String>,
+}
+
+/// Timing information of a single filter in a [`Chain`], which is exported as metrics, too.
+#[derive(Debug)]
+pub struct FilterStats {
+    runs: AtomicU64,
+    errors: AtomicU64,
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+    duration: Histogram,
+    failures: IntCounter,
+}
+
+impl FilterStats {
+    fn new(source: &str, filter: &str, position: usize) -> Self {
+        let labels = [source, filter, &position.to_string()];
+        let metrics = metrics();
+        Self {
+            runs: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            total_nanos: AtomicU64::new(0),
+            max_nanos: AtomicU64::new(0),
+            duration: metrics.filter_duration.with_label_values(&labels),
+            failures: metrics.filter_errors.with_label_values(&labels),
+        }
+    }
+
+    pub fn runs(&self) -> u64 {
+        self.runs.load(Ordering::Relaxed)
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn total_seconds(&self) -> f64 {
+        self.total_nanos.load(Ordering::Relaxed) as f64 * 1e-9
+    }
+
+    pub fn max_seconds(&self) -> f64 {
+        self.max_nanos.load(Ordering::Relaxed) as f64 * 1e-9
+    }
+
+    fn record(&self, start: Instant, success: bool) {
+        let elapsed = start.elapsed();
+        self.duration.observe(elapsed.as_secs_f64());
+        let nanos = elapsed.as_nanos().try_into().unwrap_or(u64::MAX);
+        let _ = self.runs.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            let _ = self.errors.fetch_add(1, Ordering::Relaxed);
+            self.failures.inc();
+        }
+        let _ = self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        let _ = self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+}
+
+/// An ordered list of [`FrameFilter`]s.
+#[derive(Debug)]
+pub struct Chain {
+    filters: Vec<(Box<dyn FrameFilter>, FilterStats)>,
+    /// JPEG quality of images that had to be modified
+    quality: u8,
+}
+
+impl Chain {
+    /// `source` is the name of the source, used in the metrics.
+    pub fn new(source: &str, filters: Vec<Box<dyn FrameFilter>>, quality: u8) -> Self {
+        let filters = filters
+            .into_iter()
+            .enumerate()
+            .map(|(position, filter)| {
+                let stats = FilterStats::new(source, filter.name(), position);
+                (filter, stats)
+            })
+            .collect();
+        Self { filters, quality }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Applies all filters, and returns the resulting image, or `None` if it was dropped.
+    pub fn run(&self, mut frame: FilterFrame) -> anyhow::Result<Option<Output>> {
+        for (filter, stats) in &self.filters {
+            let start = Instant::now();
+            let verdict = filter.apply(&mut frame);
+            stats.record(start, verdict.is_ok());
+            match verdict.with_context(|| format!("Filter {:?} failed", filter.name()))? {
+                Verdict::Keep => {},
+                Verdict::Drop => return Ok(None),
+            }
+        }
+        let FilterFrame {
+            headers,
+            tags,
+            route,
+            jpeg,
+            image,
+            ..
+        } = frame;
+        Ok(Some(Output {
+            headers,
+            jpeg: FilterFrame::into_jpeg(jpeg, image, self.quality)?,
+            tags,
+            route,
+        }))
+    }
+
+    pub fn stats(&self) -> impl Iterator<Item = (&'static str, &FilterStats)> {
+        self.filters
+            .iter()
+            .map(|(filter, stats)| (filter.name(), stats))
+    }
+}
+
+/// A filter as given in the configuration file or with `--filter`.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum FilterConfig {
+    /// Drop parts that are not JPEG images, or that were truncated
+    Validate(Validate),
+    /// Only keep a region of the image
+    Crop { rect: Rect },
+    /// Rotate the image clockwise
+    Rotate { degrees: Rotation },
+    /// Mirror the image
+    Flip { direction: Flip },
+    /// Black out or pixelate a region of the image
+    Mask(Mask),
+    /// Draw a logo into the image
+    Watermark(crate::watermark::Config),
+    /// Render text into the image
+    Overlay(Overlay),
+    /// Run a Rhai script
+    Script(crate::script::Config),
+}
+
+impl FilterConfig {
+    /// Parses a filter given as the content of a TOML inline table,
+    /// e.g. `type = "rotate", degrees = 90`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        #[derive(serde::Deserialize)]
+        struct Wrapper {
+            filter: FilterConfig,
+        }
+
+        let Wrapper { filter } =
+            toml::from_str(&format!("filter = {{ {s} }}")).map_err(|err| err.to_string())?;
+        Ok(filter)
+    }
+
+    /// Instantiates the filter. May block, e.g. to read files.
+    pub fn build(self) -> Result<Box<dyn FrameFilter>, Error> {
+        Ok(match self {
+            FilterConfig::Validate(validate) => Box::new(validate),
+            FilterConfig::Crop { rect } => Box::new(rect),
+            FilterConfig::Rotate { degrees } => Box::new(degrees),
+            FilterConfig::Flip { direction } => Box::new(direction),
+            FilterConfig::Mask(mask) => Box::new(mask),
+            FilterConfig::Watermark(config) => {
+                Box::new(Watermark::load(config).map_err(Error::Watermark)?)
+            },
+            FilterConfig::Overlay(overlay) => Box::new(overlay),
+            FilterConfig::Script(config) => Box::new(Script::load(config).map_err(Error::Script)?),
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Watermark(crate::watermark::Error),
+    #[error(transparent)]
+    Script(crate::script::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s| FilterConfig::parse(s).unwrap();
+        assert!(matches!(
+            parse(r#"type = "validate""#),
+            FilterConfig::Validate(_)
+        ));
+        assert!(matches!(
+            parse(r#"type = "validate", require_content_type = true"#),
+            FilterConfig::Validate(_),
+        ));
+        assert!(matches!(
+            parse(r#"type = "crop", rect = "64x48+16+8""#),
+            FilterConfig::Crop { rect } if rect == "64x48+16+8".parse().unwrap(),
+        ));
+        assert!(matches!(
+            parse(r#"type = "rotate", degrees = 270"#),
+            FilterConfig::Rotate {
+                degrees: Rotation::Rotate270,
+            },
+        ));
+        assert!(matches!(
+            parse(r#"type = "flip", direction = "vertical""#),
+            FilterConfig::Flip {
+                direction: Flip::Vertical,
+            },
+        ));
+        assert!(matches!(
+            parse(r#"type = "mask", shape = "0,0;8,0;0,8", style = "pixelate=4""#),
+            FilterConfig::Mask(_),
+        ));
+        assert!(matches!(
+            parse(r#"type = "overlay", format = "%H:%M", size = 30"#),
+            FilterConfig::Overlay(_),
+        ));
+
+        for s in [
+            "",
+            "rotate",
+            r#"degrees = 90"#,
+            r#"type = "blur""#,
+            r#"type = "rotate""#,
+            r#"type = "rotate", degrees = 45"#,
+            r#"type = "rotate", degrees = "90""#,
+            r#"type = "rotate", degrees = 90, direction = "both""#,
+            r#"type = "crop", rect = "64x48""#,
+            r#"type = "flip", direction = "diagonal""#,
+            r#"type = "mask", shape = "0,0;8,0", style = "pixelate=4""#,
+            r#"type = "overlay", format = "%Q""#,
+            r#"type = "validate" }, other = { type = "validate""#,
+        ] {
+            assert!(FilterConfig::parse(s).is_err(), "{s:?}");
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use http::HeaderMap;
+
+/// A single JPEG image, as received from the upstream.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// `body` wrapped in a `multipart/x-mixed-replace` part, ready to be streamed
+    pub part: Bytes,
+    /// The JPEG image, a zero-copy slice into `part`
+    pub body: Bytes,
+    /// When the frame was received
+    pub received: SystemTime,
+    /// Additional headers set by filters, sent along with the image
+    pub tags: HeaderMap,
+    /// Shown while the source is offline, instead of a received image
+    pub placeholder: bool,
+}
+
+impl Frame {
+    pub fn new(body: &[u8], received: SystemTime, tags: HeaderMap) -> Self {
+        let mut head = format!(
+            "\
+            Content-Length: {}\r\n\
+            Content-Type: image/jpeg\r\n",
+            body.len(),
+        )
+        .into_bytes();
+        for (name, value) in &tags {
+            head.extend(name.as_str().as_bytes());
+            head.extend(b": ");
+            head.extend(value.as_bytes());
+            head.extend(b"\r\n");
+        }
+        head.extend(b"\r\n");
+        let trailer = "--frameboundary\r\n";
+        let mut data = Vec::<u8>::with_capacity(head.len() + body.len() + trailer.len());
+        data.extend(&head);
+        data.extend(body);
+        data.extend(trailer.as_bytes());
+
+        let part = Bytes::from(data);
+        let body = part.slice(head.len()..head.len() + body.len());
+        Self {
+            part,
+            body,
+            received,
+            tags,
+            placeholder: false,
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Reads the header of JPEG images without decoding them.
+
+/// Information from the start-of-frame segment of a JPEG image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Info {
+    pub width: u16,
+    pub height: u16,
+    /// Chroma subsampling like `"4:2:0"`, or `"4:0:0"` for grayscale images
+    pub subsampling: Option<&'static str>,
+}
+
+impl Info {
+    /// Reads the start-of-frame segment, returns `None` if the data is malformed.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut data = data.strip_prefix(&[0xff, SOI])?;
+        loop {
+            // markers may be preceded by any number of fill bytes
+            let start = data.iter().position(|&b| b != 0xff)?;
+            if start == 0 {
+                return None;
+            }
+            let marker = data[start];
+            data = &data[start + 1..];
+            match marker {
+                TEM | RST0..=RST7 => continue,
+                SOI | EOI | SOS => return None,
+                _ => {},
+            }
+
+            let length = usize::from(u16::from_be_bytes(data.get(..2)?.try_into().ok()?));
+            let segment = data.get(2..length)?;
+            data = &data[length..];
+            if is_sof(marker) {
+                let [_precision, height0, height1, width0, width1, _count, ref components @ ..] =
+                    *segment
+                else {
+                    return None;
+                };
+                return Some(Self {
+                    width: u16::from_be_bytes([width0, width1]),
+                    height: u16::from_be_bytes([height0, height1]),
+                    subsampling: subsampling(components),
+                });
+            }
+        }
+    }
+}
+
+/// Names the subsampling of the usual YCbCr or grayscale images.
+///
+/// Every component is given as `[id, horizontal << 4 | vertical, quantization table]`.
+fn subsampling(components: &[u8]) -> Option<&'static str> {
+    match *components {
+        [_, 0x11, _] => Some("4:0:0"),
+        [_, luma, _, _, 0x11, _, _, 0x11, _] => match luma {
+            0x11 => Some("4:4:4"),
+            0x12 => Some("4:4:0"),
+            0x21 => Some("4:2:2"),
+            0x22 => Some("4:2:0"),
+            0x41 => Some("4:1:1"),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Start-of-frame markers, except for DHT, JPG and DAC, which share the range.
+fn is_sof(marker: u8) -> bool {
+    matches!(marker, 0xc0..=0xcf) && !matches!(marker, DHT | JPG | DAC)
+}
+
+pub const TEM: u8 = 0x01;
+pub const SOF0: u8 = 0xc0;
+pub const SOF1: u8 = 0xc1;
+pub const DHT: u8 = 0xc4;
+const JPG: u8 = 0xc8;
+const DAC: u8 = 0xcc;
+pub const RST0: u8 = 0xd0;
+pub const RST7: u8 = 0xd7;
+pub const SOI: u8 = 0xd8;
+pub const EOI: u8 = 0xd9;
+pub const SOS: u8 = 0xda;
+pub const DQT: u8 = 0xdb;
+pub const DRI: u8 = 0xdd;
+pub const APP0: u8 = 0xe0;
+pub const APP1: u8 = 0xe1;
+pub const APP15: u8 = 0xef;
+pub const COM: u8 = 0xfe;
+
+#[cfg(test)]
+mod tests {
+    use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+    use super::*;
+
+    fn encode(sampling: SamplingFactor, color_type: ColorType, progressive: bool) -> Vec<u8> {
+        let (width, height) = (40, 24);
+        let pixels = vec![0x80; width * height * 4];
+        let mut data = Vec::new();
+        let mut encoder = Encoder::new(&mut data, 80);
+        encoder.set_sampling_factor(sampling);
+        encoder.set_progressive(progressive);
+        encoder.add_app_segment(1, b"Exif\0\0").unwrap();
+        encoder
+            .encode(&pixels, width as u16, height as u16, color_type)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn parse() {
+        for (sampling, color_type, subsampling) in [
+            (SamplingFactor::R_4_4_4, ColorType::Rgb, "4:4:4"),
+            (SamplingFactor::R_4_2_2, ColorType::Rgb, "4:2:2"),
+            (SamplingFactor::R_4_2_0, ColorType::Rgb, "4:2:0"),
+            (SamplingFactor::R_4_2_0, ColorType::Luma, "4:0:0"),
+        ] {
+            for progressive in [false, true] {
+                let data = encode(sampling, color_type, progressive);
+                assert_eq!(
+                    Info::parse(&data),
+                    Some(Info {
+                        width: 40,
+                        height: 24,
+                        subsampling: Some(subsampling),
+                    }),
+                    "{subsampling}, {progressive}",
+                );
+            }
+        }
+        let data = encode(SamplingFactor::R_4_2_0, ColorType::Cmyk, false);
+        assert_eq!(Info::parse(&data).unwrap().subsampling, None);
+    }
+
+    #[test]
+    fn headers() {
+        let sof = [0xff, SOF0, 0, 11, 8, 0, 24, 0, 40, 1, 1, 0x11, 0];
+        // fill bytes, and a DHT segment, which has a marker in the range of SOF markers
+        let data = [&[0xff, SOI, 0xff, 0xff, DHT, 0, 3, 0][..], &sof].concat();
+        assert_eq!(
+            Info::parse(&data),
+            Some(Info {
+                width: 40,
+                height: 24,
+                subsampling: Some("4:0:0"),
+            }),
+        );
+
+        assert_eq!(Info::parse(&[]), None);
+        assert_eq!(Info::parse(&sof), None);
+        assert_eq!(Info::parse(&[0xff, SOI]), None);
+        assert_eq!(Info::parse(&[0xff, SOI, 0xff, SOS, 0, 2]), None);
+        assert_eq!(Info::parse(&[0xff, SOI, 0xff, EOI]), None);
+        // no marker
+        assert_eq!(Info::parse(&[0xff, SOI, 0x00, SOF0, 0, 2]), None);
+        // a segment that is shorter than its length field
+        assert_eq!(Info::parse(&[0xff, SOI, 0xff, APP0, 0, 16, 0]), None);
+        // a start of frame that is too short
+        assert_eq!(Info::parse(&[0xff, SOI, 0xff, SOF0, 0, 5, 8, 0, 24]), None);
+        for len in 0..data.len() {
+            assert_eq!(Info::parse(&data[..len]), None, "{len}");
+        }
+    }
+}
@@ -1,68 +1,549 @@
-use std::time::Duration;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::anyhow;
+use futures_util::future::{join, join_all};
 use futures_util::StreamExt;
+use http::HeaderMap;
 use mime::Mime;
 use reqwest::Url;
+use tokio::task::spawn_blocking;
 use tokio::time::sleep;
 
-use crate::image_holder;
-use crate::multipart_stream_fixed::parse;
+use crate::filter::{FilterConfig, FilterFrame, Output};
+use crate::frame::Frame;
+use crate::mask::Mask;
+use crate::metrics::metrics;
+use crate::multipart_stream_fixed::{self, parse};
+use crate::source::{self, Source, SourceConfig, State};
+use crate::transform::Transform;
 
-pub async fn listener(args: Args) -> Result<(), Error> {
-    loop {
-        let err = listener_inner(&args).await;
-        // TODO: msg
-        let _ = dbg!(err);
-        sleep(Duration::from_secs(5)).await;
+/// Restreams all sources. Never returns.
+pub async fn listener() {
+    let _ = join_all(source::sources().iter().map(|source| async move {
+        let reconnect = async {
+            loop {
+                source.set_state(State::Connecting);
+                let result = listener_inner(source).await;
+                let category = ErrorCategory::of(&result);
+                record_error(source, category, &result);
+                source.set_error(category);
+                source.set_state(State::Backoff);
+                match result {
+                    Ok(()) => eprintln!("Source {:?} ended the stream", source.name),
+                    Err(err) => eprintln!(
+                        "Source {:?} failed ({}): {}",
+                        source.name,
+                        category.name(),
+                        describe(&err),
+                    ),
+                }
+                sleep(Duration::from_secs(5)).await;
+                metrics()
+                    .reconnects
+                    .with_label_values(&[&source.name])
+                    .inc();
+            }
+        };
+        join(reconnect, crate::slate::slate(source)).await
+    }))
+    .await;
+}
+
+/// The messages of the error and its causes, on one line.
+///
+/// Causes whose message is already part of the previous message are skipped, e.g. `hyper` errors
+/// repeat the message of their source.
+fn describe(err: &anyhow::Error) -> String {
+    let mut text = String::new();
+    let mut previous = String::new();
+    for cause in err.chain() {
+        let message = cause.to_string();
+        if !previous.contains(&message) {
+            if !text.is_empty() {
+                text.push_str(": ");
+            }
+            text.push_str(&message);
+        }
+        previous = message;
+    }
+    text
+}
+
+fn record_error(source: &Source, category: ErrorCategory, result: &anyhow::Result<()>) {
+    let metrics = metrics();
+    metrics
+        .upstream_errors
+        .with_label_values(&[&source.name, category.name()])
+        .inc();
+    let Err(err) = result else {
+        return;
+    };
+    let kind = err.chain().find_map(|cause| {
+        cause
+            .downcast_ref::<multipart_stream_fixed::Error>()?
+            .kind()
+    });
+    if let Some(kind) = kind {
+        metrics
+            .parse_errors
+            .with_label_values(&[&source.name, kind.as_str()])
+            .inc();
     }
 }
 
-async fn listener_inner(args: &Args) -> anyhow::Result<()> {
-    let resp = reqwest::get(args.url.clone()).await?.error_for_status()?;
+async fn listener_inner(source: &'static Source) -> anyhow::Result<()> {
+    let resp = reqwest::get(source.url.clone()).await?.error_for_status()?;
     let content_type: Mime = resp
         .headers()
         .get(http::header::CONTENT_TYPE)
-        .ok_or_else(|| anyhow!("No content-type"))?
-        .to_str()?
-        .parse()?;
+        .ok_or(Protocol::NoContentType)?
+        .to_str()
+        .ok()
+        .and_then(|content_type| content_type.parse().ok())
+        .ok_or(Protocol::InvalidContentType)?;
     if content_type.type_() != "multipart" || content_type.subtype() != "x-mixed-replace" {
-        anyhow::bail!(r#"{content_type:?} not "multipart/x-mixed-replace""#);
+        return Err(Protocol::NotMultipart(content_type).into());
     }
     let boundary = content_type
         .get_param(mime::BOUNDARY)
-        .ok_or_else(|| anyhow!("No boundary"))?
+        .ok_or(Protocol::NoBoundary)?
         .as_str();
 
+    let mut throttle = Throttle::new(
+        source.max_input_interval,
+        source.skip_duplicates,
+        Instant::now(),
+    );
     let mut stream = parse(resp.bytes_stream(), boundary);
     while let Some(part) = stream.next().await {
-        let body = part?.body;
-
-        let head = format!(
-            "\
-            Content-Length: {}\r\n\
-            Content-Type: image/jpeg\r\n\
-            \r\n",
-            body.len(),
-        );
-        let trailer = "--frameboundary\r\n";
-        let mut data = Vec::<u8>::with_capacity(head.len() + body.len() + trailer.len());
-        data.extend(head.as_bytes());
-        data.extend(body);
-        data.extend(trailer.as_bytes());
+        let part = part?;
+        let body = part.body;
+        let received = SystemTime::now();
+        source.set_online(received, body.len());
+        let metrics = metrics();
+        metrics
+            .frames_received
+            .with_label_values(&[&source.name])
+            .inc();
+        metrics
+            .bytes_received
+            .with_label_values(&[&source.name])
+            .inc_by(body.len() as u64);
 
-        image_holder().update(data.into()).await;
+        if !throttle.admit(Instant::now(), &body) {
+            continue;
+        }
+
+        let (target, frame) = if source.chain.is_empty() {
+            (source, Frame::new(&body, received, HeaderMap::new()))
+        } else {
+            let frame = FilterFrame::new(part.headers, body, received);
+            let Some((target, output)) = run_chains(source, frame).await? else {
+                continue;
+            };
+            (target, Frame::new(&output.jpeg, received, output.tags))
+        };
+        target.publish(frame).await;
     }
     Ok(())
 }
 
+/// Drops images to cap the frame rate, and images that are identical to the previous one.
+#[derive(Debug)]
+struct Throttle {
+    /// Minimum time between two images
+    interval: Option<Duration>,
+    skip_duplicates: bool,
+    /// When the next image may be restreamed
+    next_due: Instant,
+    /// Hash of the previous image
+    last_hash: Option<u64>,
+}
+
+impl Throttle {
+    fn new(interval: Option<Duration>, skip_duplicates: bool, now: Instant) -> Self {
+        Self {
+            interval,
+            skip_duplicates,
+            next_due: now,
+            last_hash: None,
+        }
+    }
+
+    /// Whether the image that was received at `now` should be restreamed.
+    fn admit(&mut self, now: Instant, body: &[u8]) -> bool {
+        if let Some(interval) = self.interval {
+            if now < self.next_due {
+                return false;
+            }
+            // Keep the phase, so that the average rate is met even if the images arrive at odd
+            // intervals, but don't let a burst of images pass after a pause.
+            self.next_due = if now >= self.next_due + interval {
+                now + interval
+            } else {
+                self.next_due + interval
+            };
+        }
+        if self.skip_duplicates {
+            let mut hasher = DefaultHasher::new();
+            body.hash(&mut hasher);
+            let hash = hasher.finish();
+            if self.last_hash == Some(hash) {
+                return false;
+            }
+            self.last_hash = Some(hash);
+        }
+        true
+    }
+}
+
+/// Applies the filters of the source, and the filters of the stream the frame is routed to.
+///
+/// Returns `None` if the frame was dropped. Frames are routed at most once, a route set by the
+/// filters of the target stream is ignored.
+async fn run_chains(
+    source: &'static Source,
+    frame: FilterFrame,
+) -> anyhow::Result<Option<(&'static Source, Output)>> {
+    let received = frame.received;
+    let Some(output) = run_chain(source, frame).await? else {
+        return Ok(None);
+    };
+    let Some(name) = output.route.as_deref().filter(|&name| name != source.name) else {
+        return Ok(Some((source, output)));
+    };
+    let Some(target) = source::find(Some(name)) else {
+        eprintln!("Cannot route frame to unknown stream {name:?}");
+        metrics()
+            .frames_dropped
+            .with_label_values(&[&source.name, "unknown-route"])
+            .inc();
+        return Ok(None);
+    };
+    if target.chain.is_empty() {
+        return Ok(Some((target, output)));
+    }
+    // e.g. privacy masks of the target must not be bypassed
+    let mut frame = FilterFrame::new(output.headers, output.jpeg, received);
+    frame.tags = output.tags;
+    Ok(run_chain(target, frame)
+        .await?
+        .map(|output| (target, output)))
+}
+
+/// Applies the filters of the source on a blocking thread. Errors of filters are only logged.
+async fn run_chain(
+    source: &'static Source,
+    frame: FilterFrame,
+) -> Result<Option<Output>, tokio::task::JoinError> {
+    let reason = match spawn_blocking(move || source.chain.run(frame)).await? {
+        Ok(Some(output)) => return Ok(Some(output)),
+        Ok(None) => "filter",
+        Err(err) => {
+            eprintln!("{err:?}");
+            "error"
+        },
+    };
+    metrics()
+        .frames_dropped
+        .with_label_values(&[&source.name, reason])
+        .inc();
+    Ok(None)
+}
+
+/// The source given on the command line.
 #[derive(clap::Args, Debug)]
 #[command(id = "listener")]
 pub struct Args {
     /// URL to restream
+    #[arg(long, required_unless_present = "config")]
+    url: Option<Url>,
+    /// Name of the source, used in URLs like `/streams/{name}/image.jpeg`
+    #[arg(long, default_value = "default")]
+    name: String,
+    /// Drop images so that at most this many images per second get restreamed
+    #[arg(long)]
+    max_input_fps: Option<f64>,
+    /// Drop images that are identical to the previous image
     #[arg(long)]
-    url: Url,
+    skip_duplicates: bool,
+    /// Number of seconds without images until clients are shown an "offline" placeholder, while
+    /// snapshots are not available
+    #[arg(long, default_value_t = crate::source::default_offline_after())]
+    offline_after: u64,
+    /// Anyone may view the source, even if authentication is enabled
+    #[arg(long)]
+    public: bool,
+    /// User or token that may view the source, can be given multiple times; default: everyone who
+    /// authenticated
+    #[arg(long = "allow", value_name = "NAME")]
+    allow: Vec<String>,
+    #[command(flatten)]
+    transform: Transform,
+    /// Black out or pixelate a region of the transformed image,
+    /// `WIDTHxHEIGHT+X+Y` or `X,Y;X,Y;X,Y[;…]`, optionally followed by `/fill=RRGGBB` or `/pixelate=SIZE`
+    #[arg(long = "mask", value_name = "SHAPE[/STYLE]")]
+    masks: Vec<Mask>,
+    #[command(flatten)]
+    watermark: crate::watermark::Args,
+    #[command(flatten)]
+    overlay: crate::overlay::Args,
+    /// Additional filter, applied after all other modifications,
+    /// e.g. `type = "rotate", degrees = 90`
+    #[arg(long = "filter", value_name = "TOML", value_parser = FilterConfig::parse)]
+    filters: Vec<FilterConfig>,
+    /// JPEG quality of images that had to be modified
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+}
+
+impl Args {
+    /// The source, if `--url` was given.
+    pub fn source(self) -> Option<SourceConfig> {
+        let filters = self
+            .transform
+            .filters()
+            .chain(self.masks.into_iter().map(FilterConfig::Mask))
+            .chain(self.watermark.filter())
+            .chain(self.overlay.filter())
+            .chain(self.filters)
+            .collect();
+        Some(SourceConfig {
+            name: self.name,
+            url: self.url?,
+            max_input_fps: self.max_input_fps,
+            skip_duplicates: self.skip_duplicates,
+            required: true,
+            offline_after: self.offline_after,
+            public: self.public,
+            allow: (!self.allow.is_empty()).then_some(self.allow),
+            quality: self.quality,
+            filters,
+        })
+    }
+}
+
+/// Why the connection to a source was lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCategory {
+    /// Could not connect to the upstream
+    Connect,
+    /// The upstream did not answer in time
+    Timeout,
+    /// The upstream answered with an HTTP error status
+    Status,
+    /// The upstream did not send an MJPEG stream
+    Protocol,
+    /// The MJPEG stream was malformed
+    Parse,
+    /// The connection broke while receiving the stream
+    Network,
+    /// The upstream ended the stream
+    Closed,
+    /// The upstream did not send an image for too long
+    Stalled,
+    Other,
+}
+
+impl ErrorCategory {
+    fn of(result: &anyhow::Result<()>) -> Self {
+        let Err(err) = result else {
+            return ErrorCategory::Closed;
+        };
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+                return if err.is_timeout() {
+                    ErrorCategory::Timeout
+                } else if err.is_connect() {
+                    ErrorCategory::Connect
+                } else if err.is_status() {
+                    ErrorCategory::Status
+                } else {
+                    ErrorCategory::Network
+                };
+            } else if cause.is::<Protocol>() {
+                return ErrorCategory::Protocol;
+            } else if let Some(err) = cause.downcast_ref::<multipart_stream_fixed::Error>() {
+                if std::error::Error::source(err).is_none() {
+                    return ErrorCategory::Parse;
+                }
+            }
+        }
+        ErrorCategory::Other
+    }
+
+    /// Identifier of the category, as used in metrics and events.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCategory::Connect => "connect",
+            ErrorCategory::Timeout => "timeout",
+            ErrorCategory::Status => "status",
+            ErrorCategory::Protocol => "protocol",
+            ErrorCategory::Parse => "parse",
+            ErrorCategory::Network => "network",
+            ErrorCategory::Closed => "closed",
+            ErrorCategory::Stalled => "stalled",
+            ErrorCategory::Other => "other",
+        }
+    }
+
+    /// Human readable description of the category.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCategory::Connect => "connection failed",
+            ErrorCategory::Timeout => "timeout",
+            ErrorCategory::Status => "HTTP error",
+            ErrorCategory::Protocol => "not an MJPEG stream",
+            ErrorCategory::Parse => "malformed stream",
+            ErrorCategory::Network => "connection lost",
+            ErrorCategory::Closed => "stream ended",
+            ErrorCategory::Stalled => "no images received",
+            ErrorCategory::Other => "error",
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
-pub enum Error {}
+enum Protocol {
+    #[error("No content-type")]
+    NoContentType,
+    #[error("Invalid content-type")]
+    InvalidContentType,
+    #[error(r#"{0:?} not "multipart/x-mixed-replace""#)]
+    NotMultipart(Mime),
+    #[error("No boundary")]
+    NoBoundary,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use anyhow::Context;
+    use bytes::Bytes;
+
+    use super::*;
+
+    /// The offsets in milliseconds of the images `throttle` lets pass.
+    fn admitted(throttle: &mut Throttle, start: Instant, images: &[(u64, &[u8])]) -> Vec<u64> {
+        images
+            .iter()
+            .filter(|&&(ms, body)| throttle.admit(start + Duration::from_millis(ms), body))
+            .map(|&(ms, _)| ms)
+            .collect()
+    }
+
+    #[test]
+    fn decimation() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(Some(Duration::from_millis(100)), false, start);
+        let images: Vec<(u64, &[u8])> = (0..10).map(|idx| (idx * 33, &b"x"[..])).collect();
+        // 30 fps capped to 10 fps, the phase is kept
+        assert_eq!(admitted(&mut throttle, start, &images), [0, 132, 231]);
+
+        // no burst after a pause
+        let images: [(u64, &[u8]); 4] = [(1000, b"x"), (1010, b"x"), (1050, b"x"), (1100, b"x")];
+        assert_eq!(admitted(&mut throttle, start, &images), [1000, 1100]);
+    }
+
+    #[test]
+    fn unthrottled() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(None, false, start);
+        let images: [(u64, &[u8]); 3] = [(0, b"a"), (0, b"a"), (1, b"a")];
+        assert_eq!(admitted(&mut throttle, start, &images), [0, 0, 1]);
+    }
+
+    #[test]
+    fn duplicates() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(None, true, start);
+        let images: [(u64, &[u8]); 5] = [(0, b"a"), (1, b"a"), (2, b"b"), (3, b"a"), (4, b"a")];
+        assert_eq!(admitted(&mut throttle, start, &images), [0, 2, 3]);
+    }
+
+    #[test]
+    fn decimated_duplicates() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(Some(Duration::from_millis(100)), true, start);
+        // a duplicate that is dropped still uses up its slot
+        let images: [(u64, &[u8]); 4] = [(0, b"a"), (100, b"a"), (150, b"b"), (200, b"b")];
+        assert_eq!(admitted(&mut throttle, start, &images), [0, 200]);
+    }
+
+    /// Serves a single connection with `response`, returns the URL.
+    fn serve(response: &'static [u8], delay: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let _ = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 4096]).unwrap();
+            std::thread::sleep(delay);
+            let _ = stream.write_all(response);
+        });
+        url
+    }
+
+    async fn fetch(url: &str) -> anyhow::Result<()> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(500))
+            .build()?;
+        let resp = client.get(url).send().await?.error_for_status()?;
+        let _ = resp.bytes().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn error_categories() {
+        assert_eq!(ErrorCategory::of(&Ok(())), ErrorCategory::Closed);
+        assert_eq!(
+            ErrorCategory::of(&Err(anyhow::anyhow!("other"))),
+            ErrorCategory::Other,
+        );
+        let protocol = Err(Protocol::NoBoundary).context("Could not connect");
+        assert_eq!(ErrorCategory::of(&protocol), ErrorCategory::Protocol);
+
+        let input = futures_util::stream::iter([Ok::<_, std::io::Error>(Bytes::from("garbage"))]);
+        let mut stream = Box::pin(parse(input, "boundary"));
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(ErrorCategory::of(&Err(err.into())), ErrorCategory::Parse,);
+        // errors of the underlying stream are no parse errors
+        let input = futures_util::stream::iter([Err::<Bytes, _>(std::io::Error::other("reset"))]);
+        let mut stream = Box::pin(parse(input, "boundary"));
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(ErrorCategory::of(&Err(err.into())), ErrorCategory::Other,);
+
+        // nothing listens on the port of the closed listener
+        let url = format!(
+            "http://{}/",
+            TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap(),
+        );
+        assert_eq!(
+            ErrorCategory::of(&fetch(&url).await),
+            ErrorCategory::Connect,
+        );
+        let url = serve(b"HTTP/1.1 200 OK\r\n\r\n", Duration::from_secs(2));
+        assert_eq!(
+            ErrorCategory::of(&fetch(&url).await),
+            ErrorCategory::Timeout,
+        );
+        let url = serve(
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+            Duration::ZERO,
+        );
+        assert_eq!(ErrorCategory::of(&fetch(&url).await), ErrorCategory::Status,);
+        let url = serve(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\ntruncated",
+            Duration::ZERO,
+        );
+        assert_eq!(
+            ErrorCategory::of(&fetch(&url).await),
+            ErrorCategory::Network,
+        );
+    }
+}
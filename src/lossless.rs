@@ -0,0 +1,1030 @@
+//! Lossless rotation, mirroring and cropping of JPEG images, like `jpegtran` does.
+//!
+//! Instead of decoding and re-encoding the pixels, the quantized DCT coefficients are rearranged.
+//! This works for baseline images that consist of a single scan, if the edges that are moved
+//! fall on MCU boundaries. Other images have to be decoded.
+//!
+//! EXIF data is dropped, because its orientation and size would no longer match the image.
+
+use crate::jpeg::{
+    APP0, APP1, APP15, COM, DHT, DQT, DRI, EOI, RST0, RST7, SOF0, SOF1, SOI, SOS, TEM,
+};
+
+/// A change of the image that can be done without decoding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    FlipHorizontal,
+    FlipVertical,
+    /// Rotate by 90 degrees clockwise
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Keep the given region, its top left corner must be on an MCU boundary, i.e. `x` and `y`
+    /// must be multiples of 8 or 16 pixels, depending on the chroma subsampling
+    Crop {
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+    },
+}
+
+/// Applies `operation`, returns `None` if the image cannot be transformed losslessly.
+pub fn transform(data: &[u8], operation: Operation) -> Option<Vec<u8>> {
+    let mut image = Image::parse(data)?;
+    image.transform(operation)?;
+    image.encode()
+}
+
+/// A decoded, but still quantized, baseline JPEG image.
+struct Image<'a> {
+    /// APPn and COM segments except EXIF, including their markers, which are copied to the output
+    segments: Vec<&'a [u8]>,
+    /// Quantization tables in zigzag order, and whether they have 16 bit precision
+    tables: [Option<([u16; 64], bool)>; 4],
+    width: u16,
+    height: u16,
+    components: Vec<Component>,
+}
+
+struct Component {
+    id: u8,
+    /// Horizontal sampling factor
+    h: usize,
+    /// Vertical sampling factor
+    v: usize,
+    /// Quantization table
+    tq: u8,
+    /// Number of blocks per row, including the blocks that pad the image to full MCUs
+    columns: usize,
+    rows: usize,
+    /// Coefficients of the blocks row by row, each in natural order
+    blocks: Vec<[i16; 64]>,
+}
+
+impl<'a> Image<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        let mut rest = data.strip_prefix(&[0xff, SOI])?;
+        let mut image = Image {
+            segments: Vec::new(),
+            tables: [None; 4],
+            width: 0,
+            height: 0,
+            components: Vec::new(),
+        };
+        // MJPEG streams often omit the Huffman tables and use the ones of the standard
+        let mut dc_tables = [
+            Some(Decoder::new(&DC_LUMA)),
+            Some(Decoder::new(&DC_CHROMA)),
+            None,
+            None,
+        ];
+        let mut ac_tables = [
+            Some(Decoder::new(&AC_LUMA)),
+            Some(Decoder::new(&AC_CHROMA)),
+            None,
+            None,
+        ];
+        let mut restart_interval = 0;
+
+        loop {
+            let start = rest.iter().position(|&b| b != 0xff)?;
+            if start == 0 {
+                return None;
+            }
+            let segment_start = &rest[start - 1..];
+            let marker = rest[start];
+            rest = &rest[start + 1..];
+            match marker {
+                TEM | RST0..=RST7 => continue,
+                SOI | EOI => return None,
+                _ => {},
+            }
+            let length = usize::from(u16::from_be_bytes(rest.get(..2)?.try_into().ok()?));
+            let segment = rest.get(2..length)?;
+            rest = &rest[length..];
+
+            match marker {
+                APP1 if segment.starts_with(b"Exif\0\0") => {},
+                APP0..=APP15 | COM => image.segments.push(&segment_start[..length + 2]),
+                DQT => image.parse_dqt(segment)?,
+                DHT => parse_dht(segment, &mut dc_tables, &mut ac_tables)?,
+                DRI => restart_interval = usize::from(u16::from_be_bytes(segment.try_into().ok()?)),
+                SOF0 | SOF1 => image.parse_sof(segment, rest.len())?,
+                SOS => {
+                    let tables = image.parse_sos(segment, &dc_tables, &ac_tables)?;
+                    image.decode_scan(rest, &tables, restart_interval)?;
+                    return Some(image);
+                },
+                // progressive or arithmetic coding, or anything else that is not understood
+                _ => return None,
+            }
+        }
+    }
+
+    fn parse_dqt(&mut self, mut segment: &[u8]) -> Option<()> {
+        while let [pq_tq, ref rest @ ..] = *segment {
+            let wide = match pq_tq >> 4 {
+                0 => false,
+                1 => true,
+                _ => return None,
+            };
+            let mut table = [0; 64];
+            let size = if wide { 128 } else { 64 };
+            let values = rest.get(..size)?;
+            for (k, value) in table.iter_mut().enumerate() {
+                *value = match wide {
+                    true => u16::from_be_bytes([values[2 * k], values[2 * k + 1]]),
+                    false => u16::from(values[k]),
+                };
+            }
+            *self.tables.get_mut(usize::from(pq_tq & 0x0f))? = Some((table, wide));
+            segment = &rest[size..];
+        }
+        Some(())
+    }
+
+    /// `remaining` is the length of the rest of the image, which has to contain the scan.
+    fn parse_sof(&mut self, segment: &[u8], remaining: usize) -> Option<()> {
+        let [8, height0, height1, width0, width1, count, ref components @ ..] = *segment else {
+            return None;
+        };
+        if !self.components.is_empty() || components.len() != 3 * usize::from(count) {
+            return None;
+        }
+        self.height = u16::from_be_bytes([height0, height1]);
+        self.width = u16::from_be_bytes([width0, width1]);
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        for component in components.chunks_exact(3) {
+            let (h, v) = match count {
+                // the sampling factors of a single component do not matter
+                1 => (1, 1),
+                _ => (
+                    usize::from(component[1] >> 4),
+                    usize::from(component[1] & 0x0f),
+                ),
+            };
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) || component[2] > 3 {
+                return None;
+            }
+            self.components.push(Component {
+                id: component[0],
+                h,
+                v,
+                tq: component[2],
+                columns: 0,
+                rows: 0,
+                blocks: Vec::new(),
+            });
+        }
+        let (mcu_columns, mcu_rows) = self.mcus();
+        let blocks = self.components.iter().map(|c| c.h * c.v).sum::<usize>();
+        // every block takes at least two bits, so corrupt sizes cannot cause huge allocations
+        if mcu_columns * mcu_rows * blocks > 4 * remaining {
+            return None;
+        }
+        for component in &mut self.components {
+            component.columns = mcu_columns * component.h;
+            component.rows = mcu_rows * component.v;
+            component.blocks = vec![[0; 64]; component.columns * component.rows];
+        }
+        Some(())
+    }
+
+    /// Returns the Huffman tables of each component. Only scans with all components are supported.
+    fn parse_sos(
+        &self,
+        segment: &[u8],
+        dc_tables: &[Option<Decoder>; 4],
+        ac_tables: &[Option<Decoder>; 4],
+    ) -> Option<Vec<(Decoder, Decoder)>> {
+        let [count, ref rest @ ..] = *segment else {
+            return None;
+        };
+        let count = usize::from(count);
+        if self.components.is_empty() || count != self.components.len() {
+            return None;
+        }
+        let (selectors, &[0, 63, 0]) = rest.split_at_checked(2 * count)? else {
+            return None;
+        };
+        // the components must be given in the order of the frame header
+        self.components
+            .iter()
+            .zip(selectors.chunks_exact(2))
+            .map(|(component, selector)| {
+                if selector[0] != component.id {
+                    return None;
+                }
+                let dc = dc_tables.get(usize::from(selector[1] >> 4))?.clone()?;
+                let ac = ac_tables.get(usize::from(selector[1] & 0x0f))?.clone()?;
+                Some((dc, ac))
+            })
+            .collect()
+    }
+
+    fn decode_scan(
+        &mut self,
+        data: &[u8],
+        tables: &[(Decoder, Decoder)],
+        restart_interval: usize,
+    ) -> Option<()> {
+        let (mcu_columns, mcu_rows) = self.mcus();
+        let mut reader = BitReader::new(data);
+        let mut predictions = vec![0; self.components.len()];
+        for mcu in 0..mcu_columns * mcu_rows {
+            if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+                reader.restart()?;
+                predictions.fill(0);
+            }
+            let (mcu_x, mcu_y) = (mcu % mcu_columns, mcu / mcu_columns);
+            for ((component, (dc, ac)), prediction) in
+                self.components.iter_mut().zip(tables).zip(&mut predictions)
+            {
+                for y in 0..component.v {
+                    for x in 0..component.h {
+                        let row = mcu_y * component.v + y;
+                        let column = mcu_x * component.h + x;
+                        let block = &mut component.blocks[row * component.columns + column];
+                        decode_block(&mut reader, dc, ac, prediction, block)?;
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Number of MCUs per row and column.
+    fn mcus(&self) -> (usize, usize) {
+        let (width, height) = self.mcu_size();
+        (
+            usize::from(self.width).div_ceil(width),
+            usize::from(self.height).div_ceil(height),
+        )
+    }
+
+    /// Width and height of an MCU in pixels.
+    fn mcu_size(&self) -> (usize, usize) {
+        let h = self.components.iter().map(|c| c.h).max().unwrap_or(1);
+        let v = self.components.iter().map(|c| c.v).max().unwrap_or(1);
+        (8 * h, 8 * v)
+    }
+
+    fn transform(&mut self, operation: Operation) -> Option<()> {
+        let (mcu_width, mcu_height) = self.mcu_size();
+        let aligned_x = usize::from(self.width) % mcu_width == 0;
+        let aligned_y = usize::from(self.height) % mcu_height == 0;
+        // the closures map the position of a new block to the position of the old block
+        match operation {
+            Operation::FlipHorizontal if aligned_x => {
+                self.remap(false, |c, x, y| (c.columns - 1 - x, y), flip_horizontal);
+            },
+            Operation::FlipVertical if aligned_y => {
+                self.remap(false, |c, x, y| (x, c.rows - 1 - y), flip_vertical);
+            },
+            Operation::Rotate180 if aligned_x && aligned_y => {
+                self.remap(
+                    false,
+                    |c, x, y| (c.columns - 1 - x, c.rows - 1 - y),
+                    |block| flip_vertical(flip_horizontal(block)),
+                );
+            },
+            Operation::Rotate90 if aligned_x && aligned_y => {
+                self.remap(
+                    true,
+                    |c, x, y| (y, c.rows - 1 - x),
+                    |block| flip_horizontal(transpose(block)),
+                );
+            },
+            Operation::Rotate270 if aligned_x && aligned_y => {
+                self.remap(
+                    true,
+                    |c, x, y| (c.columns - 1 - y, x),
+                    |block| flip_vertical(transpose(block)),
+                );
+            },
+            Operation::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => self.crop(x, y, width, height)?,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Moves and transforms every block. If `transpose`, the width and height are swapped.
+    fn remap(
+        &mut self,
+        transpose: bool,
+        old_position: impl Fn(&Component, usize, usize) -> (usize, usize),
+        coefficients: impl Fn([i16; 64]) -> [i16; 64],
+    ) {
+        if transpose {
+            (self.width, self.height) = (self.height, self.width);
+            for (table, _) in self.tables.iter_mut().flatten() {
+                *table = transpose_zigzag(table);
+            }
+        }
+        for component in &mut self.components {
+            let (columns, rows) = match transpose {
+                true => (component.rows, component.columns),
+                false => (component.columns, component.rows),
+            };
+            let mut blocks = Vec::with_capacity(component.blocks.len());
+            for y in 0..rows {
+                for x in 0..columns {
+                    let (old_x, old_y) = old_position(component, x, y);
+                    blocks.push(coefficients(
+                        component.blocks[old_y * component.columns + old_x],
+                    ));
+                }
+            }
+            component.blocks = blocks;
+            (component.columns, component.rows) = (columns, rows);
+            if transpose {
+                (component.h, component.v) = (component.v, component.h);
+            }
+        }
+    }
+
+    fn crop(&mut self, x: u16, y: u16, width: u16, height: u16) -> Option<()> {
+        let (mcu_width, mcu_height) = self.mcu_size();
+        let (x, y) = (usize::from(x), usize::from(y));
+        if x % mcu_width != 0
+            || y % mcu_height != 0
+            || width == 0
+            || height == 0
+            || x + usize::from(width) > usize::from(self.width)
+            || y + usize::from(height) > usize::from(self.height)
+        {
+            return None;
+        }
+        let (mcu_x, mcu_y) = (x / mcu_width, y / mcu_height);
+        (self.width, self.height) = (width, height);
+        let (mcu_columns, mcu_rows) = self.mcus();
+        for component in &mut self.components {
+            let columns = mcu_columns * component.h;
+            let rows = mcu_rows * component.v;
+            let (left, top) = (mcu_x * component.h, mcu_y * component.v);
+            component.blocks = (top..top + rows)
+                .flat_map(|row| {
+                    let start = row * component.columns + left;
+                    component.blocks[start..start + columns].iter().copied()
+                })
+                .collect();
+            (component.columns, component.rows) = (columns, rows);
+        }
+        Some(())
+    }
+
+    fn encode(&self) -> Option<Vec<u8>> {
+        let mut out = vec![0xff, SOI];
+        for segment in &self.segments {
+            out.extend_from_slice(segment);
+        }
+
+        for (tq, table) in self.tables.iter().enumerate() {
+            let Some((table, wide)) = table else {
+                continue;
+            };
+            let mut segment = vec![u8::from(*wide) << 4 | tq as u8];
+            for &value in table {
+                match wide {
+                    true => segment.extend_from_slice(&value.to_be_bytes()),
+                    false => segment.push(value as u8),
+                }
+            }
+            write_segment(&mut out, DQT, &segment);
+        }
+
+        let mut segment = vec![8];
+        segment.extend_from_slice(&self.height.to_be_bytes());
+        segment.extend_from_slice(&self.width.to_be_bytes());
+        segment.push(self.components.len() as u8);
+        for component in &self.components {
+            segment.extend_from_slice(&[
+                component.id,
+                (component.h << 4 | component.v) as u8,
+                component.tq,
+            ]);
+        }
+        write_segment(&mut out, SOF0, &segment);
+
+        let mut segment = Vec::new();
+        for (class_id, table) in [
+            (0x00, &DC_LUMA),
+            (0x10, &AC_LUMA),
+            (0x01, &DC_CHROMA),
+            (0x11, &AC_CHROMA),
+        ] {
+            segment.push(class_id);
+            segment.extend_from_slice(table.counts);
+            segment.extend_from_slice(table.symbols);
+        }
+        write_segment(&mut out, DHT, &segment);
+
+        // the first component uses the luma tables, the others the chroma tables
+        let mut segment = vec![self.components.len() as u8];
+        for (idx, component) in self.components.iter().enumerate() {
+            segment.extend_from_slice(&[component.id, if idx == 0 { 0x00 } else { 0x11 }]);
+        }
+        segment.extend_from_slice(&[0, 63, 0]);
+        write_segment(&mut out, SOS, &segment);
+
+        let luma = (Encoder::new(&DC_LUMA), Encoder::new(&AC_LUMA));
+        let chroma = (Encoder::new(&DC_CHROMA), Encoder::new(&AC_CHROMA));
+        let mut writer = BitWriter {
+            out,
+            acc: 0,
+            bits: 0,
+        };
+        let mut predictions = vec![0; self.components.len()];
+        let (mcu_columns, mcu_rows) = self.mcus();
+        for mcu_y in 0..mcu_rows {
+            for mcu_x in 0..mcu_columns {
+                for (idx, (component, prediction)) in
+                    self.components.iter().zip(&mut predictions).enumerate()
+                {
+                    let (dc, ac) = if idx == 0 { &luma } else { &chroma };
+                    for y in 0..component.v {
+                        for x in 0..component.h {
+                            let row = mcu_y * component.v + y;
+                            let column = mcu_x * component.h + x;
+                            let block = &component.blocks[row * component.columns + column];
+                            encode_block(&mut writer, dc, ac, prediction, block)?;
+                        }
+                    }
+                }
+            }
+        }
+        let mut out = writer.finish();
+        out.extend_from_slice(&[0xff, EOI]);
+        Some(out)
+    }
+}
+
+fn parse_dht(
+    mut segment: &[u8],
+    dc_tables: &mut [Option<Decoder>; 4],
+    ac_tables: &mut [Option<Decoder>; 4],
+) -> Option<()> {
+    while let [tc_th, ref rest @ ..] = *segment {
+        let counts = rest.get(..16)?;
+        let total = counts.iter().map(|&n| usize::from(n)).sum::<usize>();
+        let symbols = rest.get(16..16 + total)?;
+        let decoder = Some(Decoder::new(&Table { counts, symbols }));
+        let tables = match tc_th >> 4 {
+            0 => &mut *dc_tables,
+            1 => &mut *ac_tables,
+            _ => return None,
+        };
+        *tables.get_mut(usize::from(tc_th & 0x0f))? = decoder;
+        segment = &rest[16 + total..];
+    }
+    Some(())
+}
+
+fn write_segment(out: &mut Vec<u8>, marker: u8, segment: &[u8]) {
+    out.extend_from_slice(&[0xff, marker]);
+    out.extend_from_slice(&(segment.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(segment);
+}
+
+fn decode_block(
+    reader: &mut BitReader<'_>,
+    dc: &Decoder,
+    ac: &Decoder,
+    prediction: &mut i32,
+    block: &mut [i16; 64],
+) -> Option<()> {
+    let size = dc.decode(reader)?;
+    if size > 11 {
+        return None;
+    }
+    *prediction += extend(reader.receive(size)?, size);
+    block[0] = i16::try_from(*prediction).ok()?;
+
+    let mut k = 1;
+    while k < 64 {
+        let symbol = ac.decode(reader)?;
+        let (run, size) = (usize::from(symbol >> 4), symbol & 0x0f);
+        if size == 0 {
+            match run {
+                15 => k += 16,
+                _ => break,
+            }
+            continue;
+        }
+        k += run;
+        let index = *ZIGZAG.get(k)?;
+        block[index] = i16::try_from(extend(reader.receive(size)?, size)).ok()?;
+        k += 1;
+    }
+    (k <= 64).then_some(())
+}
+
+fn encode_block(
+    writer: &mut BitWriter,
+    dc: &Encoder,
+    ac: &Encoder,
+    prediction: &mut i32,
+    block: &[i16; 64],
+) -> Option<()> {
+    let diff = i32::from(block[0]) - *prediction;
+    *prediction = i32::from(block[0]);
+    let size = category(diff);
+    if size > 11 {
+        return None;
+    }
+    writer.write_symbol(dc, size)?;
+    writer.write_value(diff, size);
+
+    let mut run = 0u8;
+    for &index in &ZIGZAG[1..] {
+        let value = i32::from(block[index]);
+        if value == 0 {
+            run += 1;
+            continue;
+        }
+        while run > 15 {
+            writer.write_symbol(ac, 0xf0)?;
+            run -= 16;
+        }
+        let size = category(value);
+        if size > 10 {
+            return None;
+        }
+        writer.write_symbol(ac, run << 4 | size)?;
+        writer.write_value(value, size);
+        run = 0;
+    }
+    if run > 0 {
+        writer.write_symbol(ac, 0x00)?;
+    }
+    Some(())
+}
+
+/// Number of bits needed for the magnitude of `value`.
+fn category(value: i32) -> u8 {
+    (32 - value.unsigned_abs().leading_zeros()) as u8
+}
+
+/// Converts the `size` bits that were read to a signed value, F.2.2.1.
+fn extend(bits: u16, size: u8) -> i32 {
+    let bits = i32::from(bits);
+    match size {
+        0 => 0,
+        _ if bits < 1 << (size - 1) => bits - (1 << size) + 1,
+        _ => bits,
+    }
+}
+
+fn flip_horizontal(mut block: [i16; 64]) -> [i16; 64] {
+    // odd horizontal frequencies change their sign
+    for (index, value) in block.iter_mut().enumerate() {
+        if index % 2 == 1 {
+            *value = -*value;
+        }
+    }
+    block
+}
+
+fn flip_vertical(mut block: [i16; 64]) -> [i16; 64] {
+    for (index, value) in block.iter_mut().enumerate() {
+        if index / 8 % 2 == 1 {
+            *value = -*value;
+        }
+    }
+    block
+}
+
+fn transpose(block: [i16; 64]) -> [i16; 64] {
+    std::array::from_fn(|index| block[index % 8 * 8 + index / 8])
+}
+
+/// Transposes a quantization table, which is given in zigzag order.
+fn transpose_zigzag(table: &[u16; 64]) -> [u16; 64] {
+    let mut natural = [0; 64];
+    for (k, &index) in ZIGZAG.iter().enumerate() {
+        natural[index % 8 * 8 + index / 8] = table[k];
+    }
+    ZIGZAG.map(|index| natural[index])
+}
+
+/// Reads the entropy-coded data of a scan.
+struct BitReader<'a> {
+    data: &'a [u8],
+    acc: u64,
+    bits: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    /// Reads bytes until at least `size` bits are available, or a marker is reached.
+    fn fill(&mut self, size: u8) -> Option<()> {
+        while self.bits < size {
+            let byte = match *self.data {
+                [0xff, 0x00, ..] => {
+                    self.data = &self.data[2..];
+                    0xff
+                },
+                // a marker ends the data of a scan or restart interval
+                [0xff, ..] | [] => return None,
+                [byte, ..] => {
+                    self.data = &self.data[1..];
+                    byte
+                },
+            };
+            self.acc = self.acc << 8 | u64::from(byte);
+            self.bits += 8;
+        }
+        Some(())
+    }
+
+    fn bit(&mut self) -> Option<u16> {
+        self.receive(1)
+    }
+
+    fn receive(&mut self, size: u8) -> Option<u16> {
+        self.fill(size)?;
+        self.bits -= size;
+        Some((self.acc >> self.bits) as u16 & ((1 << size) - 1) as u16)
+    }
+
+    /// Skips the RST marker at the end of a restart interval.
+    fn restart(&mut self) -> Option<()> {
+        self.bits = 0;
+        let start = self.data.iter().position(|&b| b != 0xff)?;
+        if start == 0 || !(RST0..=RST7).contains(&self.data[start]) {
+            return None;
+        }
+        self.data = &self.data[start + 1..];
+        Some(())
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, size: u8) {
+        self.acc = self.acc << size | (value & ((1 << size) - 1));
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            let byte = (self.acc >> self.bits) as u8;
+            self.out.push(byte);
+            if byte == 0xff {
+                self.out.push(0x00);
+            }
+        }
+    }
+
+    fn write_symbol(&mut self, encoder: &Encoder, symbol: u8) -> Option<()> {
+        let (code, size) = encoder.codes[usize::from(symbol)];
+        if size == 0 {
+            return None;
+        }
+        self.write(u32::from(code), size);
+        Some(())
+    }
+
+    /// Writes the low `size` bits of `value`, or of `value - 1` if it is negative, F.1.2.1.
+    fn write_value(&mut self, value: i32, size: u8) {
+        let value = if value < 0 { value - 1 } else { value };
+        self.write(value as u32, size);
+    }
+
+    /// Pads the last byte with one bits.
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.write(0xff, 8 - self.bits);
+        }
+        self.out
+    }
+}
+
+/// A Huffman table as given in a DHT segment.
+struct Table<'a> {
+    /// Number of codes of each length from 1 to 16 bits
+    counts: &'a [u8],
+    /// The symbols in the order of their codes
+    symbols: &'a [u8],
+}
+
+impl Table<'_> {
+    /// Every symbol with its code and the length of the code, C.2.
+    fn codes(&self) -> impl Iterator<Item = (u8, u16, u8)> + '_ {
+        let mut symbols = self.symbols.iter();
+        let mut code = 0u32;
+        (1..=16u8)
+            .zip(self.counts)
+            .flat_map(move |(size, &count)| {
+                let codes = (0..count)
+                    .map(|idx| (code + u32::from(idx), size))
+                    .collect::<Vec<_>>();
+                code = (code + u32::from(count)) << 1;
+                codes
+            })
+            .filter_map(move |(code, size)| Some((*symbols.next()?, code as u16, size)))
+    }
+}
+
+/// Decodes Huffman codes with the procedure of F.2.2.3.
+#[derive(Clone)]
+struct Decoder {
+    /// Largest code of each length, or -1 if there is none
+    max_codes: [i32; 17],
+    /// Index of the first symbol of each length in `symbols`, minus the smallest code
+    offsets: [i32; 17],
+    symbols: Vec<u8>,
+}
+
+impl Decoder {
+    fn new(table: &Table<'_>) -> Self {
+        let mut max_codes = [-1; 17];
+        let mut offsets = [0; 17];
+        let mut symbols = Vec::with_capacity(table.symbols.len());
+        for (symbol, code, size) in table.codes() {
+            let size = usize::from(size);
+            if max_codes[size] < 0 {
+                offsets[size] = symbols.len() as i32 - i32::from(code);
+            }
+            max_codes[size] = i32::from(code);
+            symbols.push(symbol);
+        }
+        Self {
+            max_codes,
+            offsets,
+            symbols,
+        }
+    }
+
+    fn decode(&self, reader: &mut BitReader<'_>) -> Option<u8> {
+        let mut code = 0;
+        for size in 1..=16 {
+            code = code << 1 | i32::from(reader.bit()?);
+            if code <= self.max_codes[size] {
+                return self
+                    .symbols
+                    .get((self.offsets[size] + code) as usize)
+                    .copied();
+            }
+        }
+        None
+    }
+}
+
+struct Encoder {
+    /// Code and its length of each symbol, a length of 0 if the symbol has no code
+    codes: [(u16, u8); 256],
+}
+
+impl Encoder {
+    fn new(table: &Table<'_>) -> Self {
+        let mut codes = [(0, 0); 256];
+        for (symbol, code, size) in table.codes() {
+            codes[usize::from(symbol)] = (code, size);
+        }
+        Self { codes }
+    }
+}
+
+/// The natural index of each coefficient in zigzag order.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// The Huffman tables of the standard, K.3, which contain codes for every possible symbol.
+const DC_LUMA: Table<'static> = Table {
+    counts: &[0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+    symbols: &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+
+const DC_CHROMA: Table<'static> = Table {
+    counts: &[0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+    symbols: &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+
+const AC_LUMA: Table<'static> = Table {
+    counts: &[0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d],
+    symbols: &[
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61,
+        0x07, 0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52,
+        0xd1, 0xf0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25,
+        0x26, 0x27, 0x28, 0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45,
+        0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64,
+        0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83,
+        0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99,
+        0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6,
+        0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3,
+        0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8,
+        0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+    ],
+};
+
+const AC_CHROMA: Table<'static> = Table {
+    counts: &[0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    symbols: &[
+        0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61,
+        0x71, 0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33,
+        0x52, 0xf0, 0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18,
+        0x19, 0x1a, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44,
+        0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63,
+        0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a,
+        0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97,
+        0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4,
+        0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca,
+        0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7,
+        0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use image::{imageops, RgbImage};
+    use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+    use super::*;
+
+    const OPERATIONS: [Operation; 6] = [
+        Operation::FlipHorizontal,
+        Operation::FlipVertical,
+        Operation::Rotate90,
+        Operation::Rotate180,
+        Operation::Rotate270,
+        Operation::Crop {
+            x: 16,
+            y: 32,
+            width: 20,
+            height: 12,
+        },
+    ];
+
+    /// Encodes an image without a symmetry, in color if `sampling` is given.
+    fn encode(
+        width: u32,
+        height: u32,
+        sampling: Option<SamplingFactor>,
+        restart_interval: u16,
+    ) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(4 * x + y) as u8, (3 * y) as u8, (x * y / 16) as u8])
+        });
+        let mut data = Vec::new();
+        let mut encoder = Encoder::new(&mut data, 90);
+        encoder.set_restart_interval(restart_interval);
+        encoder.add_app_segment(1, b"Exif\0\0MM\0*").unwrap();
+        encoder.add_app_segment(2, b"other").unwrap();
+        match sampling {
+            Some(sampling) => {
+                encoder.set_sampling_factor(sampling);
+                encoder
+                    .encode(&image, width as u16, height as u16, ColorType::Rgb)
+                    .unwrap();
+            },
+            None => {
+                let image = imageops::grayscale(&image);
+                encoder
+                    .encode(&image, width as u16, height as u16, ColorType::Luma)
+                    .unwrap();
+            },
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> RgbImage {
+        image::load_from_memory(data).unwrap().to_rgb8()
+    }
+
+    fn expected(image: &RgbImage, operation: Operation) -> RgbImage {
+        match operation {
+            Operation::FlipHorizontal => imageops::flip_horizontal(image),
+            Operation::FlipVertical => imageops::flip_vertical(image),
+            Operation::Rotate90 => imageops::rotate90(image),
+            Operation::Rotate180 => imageops::rotate180(image),
+            Operation::Rotate270 => imageops::rotate270(image),
+            Operation::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => imageops::crop_imm(image, x.into(), y.into(), width.into(), height.into())
+                .to_image(),
+        }
+    }
+
+    /// Largest difference of any channel of any pixel.
+    fn difference(a: &RgbImage, b: &RgbImage) -> u8 {
+        assert_eq!(a.dimensions(), b.dimensions());
+        a.iter()
+            .zip(b.iter())
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        for sampling in [
+            Some(SamplingFactor::R_4_4_4),
+            Some(SamplingFactor::R_4_2_0),
+            None,
+        ] {
+            for restart_interval in [0, 1, 5] {
+                let data = encode(48, 64, sampling, restart_interval);
+                let source = decode(&data);
+                for operation in OPERATIONS {
+                    let case = format!("{sampling:?}, {restart_interval}, {operation:?}");
+                    let result = transform(&data, operation).expect(&case);
+                    // only the rounding of the IDCT differs
+                    let diff = difference(&decode(&result), &expected(&source, operation));
+                    assert!(diff <= 3, "{case}: {diff}");
+                    assert!(!contains(&result, b"Exif"), "{case}");
+                    assert!(contains(&result, b"other"), "{case}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unaligned() {
+        let crop = |x, y| Operation::Crop {
+            x,
+            y,
+            width: 16,
+            height: 16,
+        };
+        let data = encode(40, 64, Some(SamplingFactor::R_4_2_0), 0);
+        assert!(transform(&data, Operation::FlipHorizontal).is_none());
+        assert!(transform(&data, Operation::FlipVertical).is_some());
+        assert!(transform(&data, Operation::Rotate90).is_none());
+        assert!(transform(&data, crop(16, 16)).is_some());
+        assert!(transform(&data, crop(8, 16)).is_none());
+        assert!(transform(&data, crop(16, 8)).is_none());
+        // exceeds the image
+        assert!(transform(&data, crop(32, 0)).is_none());
+
+        let data = encode(40, 64, Some(SamplingFactor::R_4_4_4), 0);
+        assert!(transform(&data, Operation::FlipHorizontal).is_some());
+        assert!(transform(&data, crop(8, 8)).is_some());
+    }
+
+    #[test]
+    fn truncated() {
+        for restart_interval in [0, 2] {
+            let data = encode(48, 64, Some(SamplingFactor::R_4_2_0), restart_interval);
+            for len in 0..data.len() - 2 {
+                let result = transform(&data[..len], Operation::Rotate90);
+                // the end of the scan may only contain padding
+                assert!(result.is_none() || len > data.len() - 4, "{len}");
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        let data = encode(48, 64, Some(SamplingFactor::R_4_2_0), 3);
+        for idx in 0..data.len() {
+            for pattern in [0x01, 0x80, 0xff] {
+                let mut data = data.clone();
+                data[idx] ^= pattern;
+                // must not panic
+                let _ = transform(&data, Operation::Rotate270);
+            }
+        }
+
+        let sof = data.windows(2).position(|w| w == [0xff, SOF0]).unwrap();
+        let mut progressive = data.clone();
+        progressive[sof + 1] = 0xc2;
+        assert!(transform(&progressive, Operation::Rotate90).is_none());
+        // must not allocate gigabytes for the blocks
+        let mut huge = data.clone();
+        huge[sof + 5..sof + 9].fill(0xff);
+        assert!(transform(&huge, Operation::Rotate90).is_none());
+
+        assert!(transform(b"", Operation::Rotate90).is_none());
+        assert!(transform(&[0xff, SOI, 0xff, EOI], Operation::Rotate90).is_none());
+        assert!(transform(&[0xff; 64], Operation::Rotate90).is_none());
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|window| window == needle)
+    }
+}
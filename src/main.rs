@@ -19,47 +19,116 @@
 #![warn(unused_lifetimes)]
 #![warn(unused_results)]
 
+mod auth;
+mod config;
+mod events;
+mod filter;
+mod frame;
+mod jpeg;
 mod listener;
+mod lossless;
+mod mask;
+mod metrics;
 mod multipart_stream_fixed;
+mod overlay;
+mod script;
 mod sender;
+mod slate;
+mod source;
+mod systemd;
+mod tls;
+mod transform;
+mod uds;
 mod update_stream;
+mod validate;
+mod variants;
+mod viewer;
+mod watermark;
 
+use std::fmt::Display;
+use std::path::PathBuf;
 use std::process::abort;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use bytes::Bytes;
 use clap::Parser;
-use once_cell::sync::OnceCell;
+use image::Rgba;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
 use tokio::select;
+use tokio::signal::unix::{signal, Signal, SignalKind};
 use tokio::sync::oneshot;
 
+use self::config::Config;
 use self::listener::listener;
 use self::sender::sender;
-use self::update_stream::UpdateStream;
+use self::source::Source;
 
 fn main() -> Result<(), Error> {
     let args = Args::parse();
+    if let Some(Command::Sign(args)) = args.command {
+        return self::auth::sign(args).map_err(Error::Sign);
+    }
+
+    // before any thread is started, see `systemd::listen_fds()`
+    let inherited = systemd::listen_fds().map_err(Error::Systemd)?;
+
+    let mut config = match &args.config {
+        Some(path) => Config::load(path).map_err(Error::Config)?,
+        None => Config::default(),
+    };
+    if let Some(source) = args.listener.source() {
+        // the source given on the command line is the default source
+        config.sources.insert(0, source);
+    }
+    let sources = config
+        .sources
+        .into_iter()
+        .map(Source::new)
+        .collect::<Result<_, _>>()
+        .map_err(Error::Source)?;
+    source::init(sources).map_err(Error::Source)?;
 
     let (tx, rx) = oneshot::channel();
     let mut tx = Some(tx);
     ctrlc::try_set_handler(move || trapped_ctrl_c(&mut tx)).map_err(Error::CtrlC)?;
 
-    let listener = listener(args.listener);
-    let sender = sender(args.sender);
+    let listener = listener();
+    let sender = sender(args.sender, config.listeners, inherited);
     tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .map_err(Error::Rt)?
         .block_on(async move {
-            select! {
+            let mut terminate = signal(SignalKind::terminate()).map_err(Error::Signal)?;
+            let hangup = signal(SignalKind::hangup()).map_err(Error::Signal)?;
+            let result = select! {
                 biased;
-                _ = rx => (),
-                result = listener => result.map_err(Error::Listener)?,
-                result = sender => result.map_err(Error::Sender)?,
-            }
-            Ok(())
+                _ = rx => Ok(()),
+                _ = terminate.recv() => {
+                    eprintln!("Trapped shutdown signal.");
+                    Ok(())
+                },
+                () = reload_on_hangup(hangup) => Ok(()),
+                () = listener => Ok(()),
+                result = sender => result.map_err(Error::Sender),
+                () = systemd::watchdog() => Ok(()),
+            };
+            // on errors, too
+            systemd::notify_stopping();
+            result
         })
 }
 
+/// Reloads the TLS certificates on SIGHUP, which is ignored if TLS is not enabled. Never returns.
+async fn reload_on_hangup(mut hangup: Signal) {
+    while hangup.recv().await.is_some() {
+        eprintln!("Trapped SIGHUP.");
+        tls::reload();
+    }
+    std::future::pending().await
+}
+
 fn trapped_ctrl_c(tx: &mut Option<oneshot::Sender<()>>) {
     eprintln!("Trapped shutdown signal.");
     let Some(tx) = tx.take() else {
@@ -69,29 +138,118 @@ fn trapped_ctrl_c(tx: &mut Option<oneshot::Sender<()>>) {
     let _ = tx.send(());
 }
 
-fn image_holder() -> &'static UpdateStream<Bytes> {
-    static HOLDER: OnceCell<UpdateStream<Bytes>> = OnceCell::new();
-    HOLDER.get_or_init(Default::default)
+fn fps_to_interval(fps: f64) -> Result<Duration, String> {
+    if !(fps.is_finite() && fps > 0.0) {
+        return Err(format!("Frame rate must be a positive number, not {fps:?}"));
+    }
+    match Duration::try_from_secs_f64(1.0 / fps) {
+        Ok(interval) if interval <= MAX_INTERVAL => Ok(interval),
+        _ => Err(format!(
+            "Frame rate must be at least one image per day, not {fps:?}"
+        )),
+    }
+}
+
+/// Longest time between two images, so that adding it to an `Instant` cannot overflow.
+const MAX_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn millis_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn parse_fps(fps: &str) -> Result<Duration, String> {
+    fps_to_interval(fps.parse().map_err(|err| format!("{err}"))?)
+}
+
+/// Parses a color given as `RRGGBB` or `RRGGBBAA`.
+fn parse_color(value: &str) -> Result<Rgba<u8>, String> {
+    let digits = value.strip_prefix('#').unwrap_or(value);
+    if !matches!(digits.len(), 6 | 8) || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("Expected RRGGBB or RRGGBBAA, got {value:?}"));
+    }
+    let color = u32::from_str_radix(digits, 16).unwrap();
+    let color = match digits.len() {
+        6 => color << 8 | 0xff,
+        _ => color,
+    };
+    Ok(Rgba(color.to_be_bytes()))
+}
+
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(D::Error::custom)
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rgba<u8>, D::Error> {
+    parse_color(&String::deserialize(deserializer)?).map_err(D::Error::custom)
 }
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 #[group(id = "crate")]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML file that configures additional sources and listeners
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
     #[command(flatten)]
     listener: self::listener::Args,
     #[command(flatten)]
     sender: self::sender::Args,
 }
 
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Print a signed URL of a stream that expires after a while
+    Sign(self::auth::SignArgs),
+}
+
 #[derive(thiserror::Error, pretty_error_debug::Debug)]
 enum Error {
     #[error("Could not start Tokio runtime")]
     Rt(#[source] std::io::Error),
+    #[error("Could not load configuration")]
+    Config(#[source] self::config::Error),
+    #[error("Could not prepare sources")]
+    Source(#[source] self::source::Error),
     #[error("Could not set Ctrl+C handler")]
     CtrlC(#[source] ctrlc::Error),
+    #[error("Could not set signal handler")]
+    Signal(#[source] std::io::Error),
     #[error("The server part failed")]
     Sender(#[source] self::sender::Error),
-    #[error("The client part failed")]
-    Listener(#[source] self::listener::Error),
+    #[error("Could not take the sockets passed by systemd")]
+    Systemd(#[source] self::systemd::Error),
+    #[error("Could not sign URL")]
+    Sign(#[source] self::auth::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_rates() {
+        assert_eq!(fps_to_interval(4.0), Ok(Duration::from_millis(250)));
+        assert_eq!(fps_to_interval(1.0 / 86400.0), Ok(MAX_INTERVAL));
+        assert!(fps_to_interval(1e-20).is_err());
+        assert!(fps_to_interval(f64::MIN_POSITIVE).is_err());
+        assert!(fps_to_interval(5e-324).is_err());
+        assert!(fps_to_interval(0.0).is_err());
+        assert!(fps_to_interval(-1.0).is_err());
+        assert!(fps_to_interval(f64::NAN).is_err());
+        assert!(fps_to_interval(f64::INFINITY).is_err());
+        assert!(fps_to_interval(f64::NEG_INFINITY).is_err());
+        assert!(parse_fps("1e-20").is_err());
+        assert!(parse_fps("fast").is_err());
+    }
 }
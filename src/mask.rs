@@ -0,0 +1,347 @@
+use std::str::FromStr;
+
+use image::{Rgb, RgbImage, Rgba};
+
+use crate::filter::{FilterFrame, FrameFilter, Verdict};
+use crate::transform::Rect;
+
+/// A region of the image that gets blacked out or pixelated.
+///
+/// Given as `SHAPE[/STYLE]`, where `SHAPE` is a rectangle `WIDTHxHEIGHT+X+Y` or a polygon
+/// `X,Y;X,Y;X,Y[;…]`, and `STYLE` is `fill=RRGGBB` (default: `fill=000000`) or `pixelate=SIZE`.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Mask {
+    shape: Shape,
+    #[serde(default)]
+    style: Style,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+enum Shape {
+    Rect(Rect),
+    Polygon(Vec<(f32, f32)>),
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+enum Style {
+    Fill(Rgb<u8>),
+    Pixelate(u32),
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style::Fill(Rgb([0, 0, 0]))
+    }
+}
+
+impl FrameFilter for Mask {
+    fn name(&self) -> &'static str {
+        "mask"
+    }
+
+    fn apply(&self, frame: &mut FilterFrame) -> anyhow::Result<Verdict> {
+        self.draw(frame.image()?);
+        Ok(Verdict::Keep)
+    }
+}
+
+impl Mask {
+    fn draw(&self, image: &mut RgbImage) {
+        let (x0, y0, x1, y1) = self.shape.bounds();
+        let (x1, y1) = (x1.min(image.width()), y1.min(image.height()));
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+
+        match self.style {
+            Style::Fill(color) => {
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        if self.shape.contains(x, y) {
+                            image.put_pixel(x, y, color);
+                        }
+                    }
+                }
+            },
+            Style::Pixelate(size) => {
+                for by in (y0..y1).step_by(size as usize) {
+                    for bx in (x0..x1).step_by(size as usize) {
+                        let ex = bx.saturating_add(size).min(x1);
+                        let ey = by.saturating_add(size).min(y1);
+                        // only average the pixels that get replaced, so that nothing leaks
+                        let mut sum = [0u64; 3];
+                        let mut count = 0;
+                        for y in by..ey {
+                            for x in bx..ex {
+                                if self.shape.contains(x, y) {
+                                    let Rgb(pixel) = image.get_pixel(x, y);
+                                    for (sum, &value) in sum.iter_mut().zip(pixel) {
+                                        *sum += u64::from(value);
+                                    }
+                                    count += 1;
+                                }
+                            }
+                        }
+                        if count == 0 {
+                            continue;
+                        }
+                        let color = Rgb(sum.map(|sum| (sum / count) as u8));
+                        for y in by..ey {
+                            for x in bx..ex {
+                                if self.shape.contains(x, y) {
+                                    image.put_pixel(x, y, color);
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl Shape {
+    /// Bounding box as `(x0, y0, x1, y1)`, right and bottom exclusive.
+    fn bounds(&self) -> (u32, u32, u32, u32) {
+        match self {
+            Shape::Rect(rect) => (
+                rect.x,
+                rect.y,
+                rect.x.saturating_add(rect.width),
+                rect.y.saturating_add(rect.height),
+            ),
+            Shape::Polygon(points) => {
+                let (mut x0, mut y0, mut x1, mut y1) = (f32::MAX, f32::MAX, 0f32, 0f32);
+                for &(x, y) in points {
+                    (x0, y0) = (x0.min(x), y0.min(y));
+                    (x1, y1) = (x1.max(x), y1.max(y));
+                }
+                (x0 as u32, y0 as u32, x1.ceil() as u32, y1.ceil() as u32)
+            },
+        }
+    }
+
+    /// Tests if the center of the pixel `(x, y)` is inside the shape.
+    fn contains(&self, x: u32, y: u32) -> bool {
+        let Shape::Polygon(points) = self else {
+            return true; // `bounds()` is exact for rectangles
+        };
+
+        // even-odd rule
+        let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+        let mut inside = false;
+        let mut prev = points[points.len() - 1];
+        for &cur in points {
+            let ((x0, y0), (x1, y1)) = (prev, cur);
+            if (y0 > y) != (y1 > y) && x < x0 + (y - y0) / (y1 - y0) * (x1 - x0) {
+                inside = !inside;
+            }
+            prev = cur;
+        }
+        inside
+    }
+}
+
+impl FromStr for Mask {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (shape, style) = match s.split_once('/') {
+            Some((shape, style)) => (shape, style.parse()?),
+            None => (s, Style::default()),
+        };
+        Ok(Self {
+            shape: shape.parse()?,
+            style,
+        })
+    }
+}
+
+impl FromStr for Shape {
+    type Err = String;
+
+    fn from_str(shape: &str) -> Result<Self, Self::Err> {
+        if !shape.contains(';') {
+            return Ok(Shape::Rect(shape.parse()?));
+        }
+
+        let points = shape
+            .split(';')
+            .map(|point| {
+                let (x, y) = point
+                    .split_once(',')
+                    .ok_or_else(|| format!("Expected X,Y, got {point:?}"))?;
+                let x = x.parse().map_err(|_| format!("Bad coordinate {x:?}"))?;
+                let y = y.parse().map_err(|_| format!("Bad coordinate {y:?}"))?;
+                Ok((x, y))
+            })
+            .collect::<Result<Vec<(f32, f32)>, String>>()?;
+        if points.len() < 3 {
+            return Err("A polygon needs at least three points".to_owned());
+        }
+        if points
+            .iter()
+            .any(|&(x, y)| !(x >= 0.0 && y >= 0.0 && x.is_finite() && y.is_finite()))
+        {
+            return Err("Coordinates must not be negative".to_owned());
+        }
+        Ok(Shape::Polygon(points))
+    }
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(style: &str) -> Result<Self, Self::Err> {
+        let (key, value) = style
+            .split_once('=')
+            .ok_or_else(|| format!("Expected fill=RRGGBB or pixelate=SIZE, got {style:?}"))?;
+        match key {
+            "fill" => match crate::parse_color(value)? {
+                Rgba([r, g, b, 0xff]) => Ok(Style::Fill(Rgb([r, g, b]))),
+                _ => Err("Masks must be opaque".to_owned()),
+            },
+            "pixelate" => match value.parse() {
+                Ok(size) if size > 0 => Ok(Style::Pixelate(size)),
+                _ => Err(format!("Expected a positive block size, got {value:?}")),
+            },
+            _ => Err(format!("Unknown mask style {key:?}")),
+        }
+    }
+}
+
+impl TryFrom<String> for Shape {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl TryFrom<String> for Style {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: &str = "0,0;8,0;0,8";
+
+    fn rect(width: u32, height: u32, x: u32, y: u32) -> Shape {
+        Shape::Rect(Rect {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("4x3+2+1".parse(), Ok(rect(4, 3, 2, 1)));
+        assert_eq!(
+            "0,0;10.5,0;5,7".parse(),
+            Ok(Shape::Polygon(vec![(0.0, 0.0), (10.5, 0.0), (5.0, 7.0)])),
+        );
+        for shape in [
+            "",
+            "4x3",
+            "0,0;1,1",
+            "0,0;1;1,1",
+            "0,0;1,1;x,2",
+            "0,0;1,1;-1,2",
+            "0,0;1,1;NaN,2",
+            "0,0;1,1;inf,2",
+        ] {
+            assert!(shape.parse::<Shape>().is_err(), "{shape:?}");
+        }
+
+        let mask: Mask = "4x3+2+1".parse().unwrap();
+        assert_eq!(mask.style, Style::Fill(Rgb([0, 0, 0])));
+        let mask: Mask = "4x3+2+1/fill=#ff8000".parse().unwrap();
+        assert_eq!(mask.style, Style::Fill(Rgb([0xff, 0x80, 0])));
+        let mask: Mask = format!("{TRIANGLE}/pixelate=4").parse().unwrap();
+        assert_eq!(mask.style, Style::Pixelate(4));
+        for style in [
+            "fill",
+            "fill=red",
+            "fill=ff800080",
+            "pixelate=0",
+            "pixelate=-1",
+            "blur=4",
+        ] {
+            assert!(style.parse::<Style>().is_err(), "{style:?}");
+        }
+    }
+
+    #[test]
+    fn contains() {
+        let triangle: Shape = TRIANGLE.parse().unwrap();
+        assert_eq!(triangle.bounds(), (0, 0, 8, 8));
+        assert!(triangle.contains(0, 0));
+        assert!(triangle.contains(6, 0));
+        assert!(triangle.contains(3, 3));
+        assert!(!triangle.contains(4, 4));
+        assert!(!triangle.contains(7, 7));
+        assert!(!triangle.contains(8, 0));
+
+        // concave, with a notch at the top
+        let notched: Shape = "0,0;2,0;2,2;4,2;4,0;6,0;6,6;0,6".parse().unwrap();
+        assert!(notched.contains(1, 1));
+        assert!(!notched.contains(3, 1));
+        assert!(notched.contains(3, 3));
+        assert!(notched.contains(5, 1));
+
+        let rect = rect(4, 3, 2, 1);
+        assert_eq!(rect.bounds(), (2, 1, 6, 4));
+        assert_eq!(
+            self::rect(u32::MAX, u32::MAX, 2, 1).bounds(),
+            (2, 1, u32::MAX, u32::MAX),
+        );
+    }
+
+    /// Pixelates a white image with a black left half.
+    fn pixelate(mask: &str) -> RgbImage {
+        let mut image = RgbImage::from_fn(10, 10, |x, _| match x < 5 {
+            true => Rgb([0, 0, 0]),
+            false => Rgb([255, 255, 255]),
+        });
+        mask.parse::<Mask>().unwrap().draw(&mut image);
+        image
+    }
+
+    #[test]
+    fn pixelation() {
+        let image = pixelate("10x10+0+0/pixelate=4");
+        let row = (0..10)
+            .map(|x| image.get_pixel(x, 9)[0])
+            .collect::<Vec<_>>();
+        // the blocks at the right and bottom edges are smaller
+        assert_eq!(row, [0, 0, 0, 0, 191, 191, 191, 191, 255, 255]);
+        assert_eq!(image.get_pixel(4, 0), image.get_pixel(4, 9));
+
+        let image = pixelate("100x100+4+4/pixelate=4000000000");
+        // one black column and five white ones
+        assert_eq!(image.get_pixel(4, 4)[0], 212);
+        assert_eq!(image.get_pixel(9, 9)[0], 212);
+        assert_eq!(image.get_pixel(3, 3)[0], 0);
+        assert_eq!(image.get_pixel(9, 3)[0], 255);
+
+        // pixels outside of the polygon are neither changed nor used
+        // the bounding box covers the black half, but the polygon only a sliver of it
+        let image = pixelate("0,0;10,0;10,10;5,10;5,0.5/pixelate=10");
+        assert!(image.enumerate_pixels().all(|(x, _, pixel)| pixel[0]
+            == match x < 5 {
+                true => 0,
+                false => 255,
+            }));
+    }
+}
@@ -0,0 +1,211 @@
+//! Prometheus metrics, all labelled with the name of the source.
+
+use std::time::SystemTime;
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::frame::Frame;
+use crate::source::{self, Source, State};
+
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    /// Parts received from the upstream, before any filter
+    pub frames_received: IntCounterVec,
+    pub bytes_received: IntCounterVec,
+    /// Images made available to clients, after all filters
+    pub frames_published: IntCounterVec,
+    /// By source and reason
+    pub frames_dropped: IntCounterVec,
+    /// By source and endpoint
+    pub frames_sent: IntCounterVec,
+    /// By source and endpoint
+    pub bytes_sent: IntCounterVec,
+    /// By source and kind
+    pub parse_errors: IntCounterVec,
+    /// By source and category
+    pub upstream_errors: IntCounterVec,
+    pub reconnects: IntCounterVec,
+    pub clients_total: IntCounterVec,
+    /// By source and endpoint
+    pub latency: HistogramVec,
+    /// By source, filter and position of the filter
+    pub filter_duration: HistogramVec,
+    /// By source, filter and position of the filter
+    pub filter_errors: IntCounterVec,
+    state: IntGaugeVec,
+    clients: IntGaugeVec,
+    subscribers: IntGaugeVec,
+}
+
+pub fn metrics() -> &'static Metrics {
+    static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let gauge = |name: &str, help: &str, labels: &[&str]| {
+            let gauge = IntGaugeVec::new(Opts::new(name, help), labels).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+
+        let frames_received = counter(
+            "mjpeg_frames_received_total",
+            "Images received from the upstream",
+            &["source"],
+        );
+        let bytes_received = counter(
+            "mjpeg_bytes_received_total",
+            "Size of the images received from the upstream",
+            &["source"],
+        );
+        let frames_published = counter(
+            "mjpeg_frames_published_total",
+            "Images made available to clients, after dropping and filtering",
+            &["source"],
+        );
+        let frames_dropped = counter(
+            "mjpeg_frames_dropped_total",
+            "Images dropped by a filter, because a filter failed, or because they were routed to \
+             an unknown stream",
+            &["source", "reason"],
+        );
+        let frames_sent = counter("mjpeg_frames_sent_total", "Images sent to clients", &[
+            "source", "endpoint",
+        ]);
+        let bytes_sent = counter(
+            "mjpeg_bytes_sent_total",
+            "Size of the images sent to clients",
+            &["source", "endpoint"],
+        );
+        let parse_errors = counter(
+            "mjpeg_parse_errors_total",
+            "Malformed multipart streams received from the upstream",
+            &["source", "kind"],
+        );
+        let upstream_errors = counter(
+            "mjpeg_upstream_errors_total",
+            "Lost connections to the upstream",
+            &["source", "category"],
+        );
+        let reconnects = counter(
+            "mjpeg_reconnects_total",
+            "Connection attempts after a lost connection",
+            &["source"],
+        );
+        let clients_total = counter("mjpeg_clients_total", "Clients that streamed images", &[
+            "source",
+        ]);
+        let state = gauge(
+            "mjpeg_upstream_state",
+            "1 for the current state of the connection to the upstream",
+            &["source", "state"],
+        );
+        let clients = gauge("mjpeg_clients", "Clients that currently stream images", &[
+            "source",
+        ]);
+        let subscribers = gauge(
+            "mjpeg_subscribers",
+            "Tasks that currently wait for new images",
+            &["source"],
+        );
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "mjpeg_latency_seconds",
+                "Time between receiving an image and sending it to a client",
+            )
+            .buckets(exponential_buckets(0.001, 2.0, 14).unwrap()),
+            &["source", "endpoint"],
+        )
+        .unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        let filter_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "mjpeg_filter_duration_seconds",
+                "Time a filter took to process an image",
+            )
+            .buckets(exponential_buckets(0.0001, 2.0, 16).unwrap()),
+            &["source", "filter", "position"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(filter_duration.clone()))
+            .unwrap();
+        let filter_errors = counter(
+            "mjpeg_filter_errors_total",
+            "Images a filter failed to process",
+            &["source", "filter", "position"],
+        );
+
+        Self {
+            registry,
+            frames_received,
+            bytes_received,
+            frames_published,
+            frames_dropped,
+            frames_sent,
+            bytes_sent,
+            parse_errors,
+            upstream_errors,
+            reconnects,
+            clients_total,
+            latency,
+            filter_duration,
+            filter_errors,
+            state,
+            clients,
+            subscribers,
+        }
+    }
+
+    /// Records that `frame` of `source` was sent to a client of `endpoint`.
+    pub fn sent(&self, source: &Source, endpoint: &str, frame: &Frame, bytes: usize) {
+        let labels = [source.name.as_str(), endpoint];
+        self.frames_sent.with_label_values(&labels).inc();
+        self.bytes_sent
+            .with_label_values(&labels)
+            .inc_by(bytes as u64);
+        if let Ok(latency) = SystemTime::now().duration_since(frame.received) {
+            self.latency
+                .with_label_values(&labels)
+                .observe(latency.as_secs_f64());
+        }
+    }
+
+    /// All metrics in the Prometheus text format.
+    pub fn encode(&self) -> (String, Vec<u8>) {
+        for source in source::sources() {
+            let name = source.name.as_str();
+            let current = source.status().state;
+            for state in [State::Connecting, State::Streaming, State::Backoff] {
+                self.state
+                    .with_label_values(&[name, state.name()])
+                    .set((state == current).into());
+            }
+            self.clients
+                .with_label_values(&[name])
+                .set(source.clients() as i64);
+            self.subscribers
+                .with_label_values(&[name])
+                .set(source.holder.subscribers() as i64);
+        }
+
+        let encoder = TextEncoder::new();
+        let mut data = Vec::new();
+        // only fails for invalid metrics, which are caught by `Registry::register`
+        encoder.encode(&self.registry.gather(), &mut data).unwrap();
+        (encoder.format_type().to_owned(), data)
+    }
+}
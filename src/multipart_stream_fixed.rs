@@ -37,14 +37,46 @@ pub struct Error(ErrorInt);
 
 #[derive(Debug)]
 enum ErrorInt {
-    ParseError(String),
+    ParseError(ErrorKind, String),
     Underlying(Box<dyn std::error::Error + Send + Sync>),
 }
 
+/// What part of the stream could not be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Boundary,
+    Headers,
+    ContentLength,
+    TooLarge,
+    Eof,
+}
+
+impl ErrorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Boundary => "boundary",
+            ErrorKind::Headers => "headers",
+            ErrorKind::ContentLength => "content-length",
+            ErrorKind::TooLarge => "too-large",
+            ErrorKind::Eof => "eof",
+        }
+    }
+}
+
+impl Error {
+    /// The kind of the parse error, or `None` if reading the underlying stream failed.
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self.0 {
+            ErrorInt::ParseError(kind, _) => Some(kind),
+            ErrorInt::Underlying(_) => None,
+        }
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.0 {
-            ErrorInt::ParseError(ref s) => f.pad(s),
+            ErrorInt::ParseError(_, ref s) => f.pad(s),
             ErrorInt::Underlying(ref e) => e.fmt(f),
         }
     }
@@ -61,8 +93,8 @@ impl std::error::Error for Error {
 
 /// Creates a parse error with the specified format string and arguments.
 macro_rules! parse_err {
-    ($($arg:tt)*) => {
-        Error(ErrorInt::ParseError(format!($($arg)*)))
+    ($kind:ident, $($arg:tt)*) => {
+        Error(ErrorInt::ParseError(ErrorKind::$kind, format!($($arg)*)))
     };
 }
 
@@ -137,7 +169,7 @@ impl State {
                 State::Boundary { ref mut pos } => {
                     let len = std::cmp::min(boundary.len() - *pos, buf.len());
                     if buf[0..len] != boundary[*pos..*pos + len] {
-                        return Err(parse_err!("bad boundary"));
+                        return Err(parse_err!(Boundary, "bad boundary"));
                     }
                     buf.advance(len);
                     *pos += len;
@@ -149,16 +181,16 @@ impl State {
                 State::Headers => {
                     let mut raw = [httparse::EMPTY_HEADER; 16];
                     let headers = httparse::parse_headers(buf, &mut raw)
-                        .map_err(|e| parse_err!("Part headers invalid: {}", e))?;
+                        .map_err(|e| parse_err!(Headers, "Part headers invalid: {}", e))?;
                     match headers {
                         httparse::Status::Complete((body_pos, raw)) => {
                             let mut headers = HeaderMap::with_capacity(raw.len());
                             for h in raw {
                                 let _ = headers.append(
                                     HeaderName::from_bytes(h.name.as_bytes())
-                                        .map_err(|_| parse_err!("bad header name"))?,
+                                        .map_err(|_| parse_err!(Headers, "bad header name"))?,
                                     HeaderValue::from_bytes(h.value)
-                                        .map_err(|_| parse_err!("bad header value"))?,
+                                        .map_err(|_| parse_err!(Headers, "bad header value"))?,
                                 );
                             }
                             buf.advance(body_pos);
@@ -166,15 +198,24 @@ impl State {
                                 .get(header::CONTENT_LENGTH)
                                 .map(|v| v.to_str())
                                 .transpose()
-                                .map_err(|_| parse_err!("Part Content-Length is not valid string"))?
+                                .map_err(|_| {
+                                    parse_err!(
+                                        ContentLength,
+                                        "Part Content-Length is not valid string"
+                                    )
+                                })?
                                 .map(|v| v.parse())
                                 .transpose()
                                 .map_err(|_| {
-                                    parse_err!("Part Content-Length is not valid usize")
+                                    parse_err!(
+                                        ContentLength,
+                                        "Part Content-Length is not valid usize"
+                                    )
                                 })?;
                             if let Some(body_len) = body_len {
                                 if body_len > max_body_bytes {
                                     return Err(parse_err!(
+                                        TooLarge,
                                         "body byte length {} exceeds maximum of {}",
                                         body_len,
                                         max_body_bytes
@@ -186,6 +227,7 @@ impl State {
                         httparse::Status::Partial => {
                             if buf.len() >= max_header_bytes {
                                 return Err(parse_err!(
+                                    TooLarge,
                                     "incomplete {}-byte header, vs maximum of {} bytes",
                                     buf.len(),
                                     max_header_bytes
@@ -204,6 +246,7 @@ impl State {
                             *body_len = Some(n);
                         } else if buf.len() > max_body_bytes {
                             return Err(parse_err!(
+                                TooLarge,
                                 "body byte length {} exceeds maximum of {}",
                                 buf.len(),
                                 max_body_bytes
@@ -311,7 +354,7 @@ where
                 Poll::Ready(None) => {
                     if !matches!(*this.state, State::Newlines) {
                         *this.state = State::Done;
-                        return Poll::Ready(Some(Err(parse_err!("unexpected mid-part EOF"))));
+                        return Poll::Ready(Some(Err(parse_err!(Eof, "unexpected mid-part EOF"))));
                     }
                     return Poll::Ready(None);
                 },
@@ -0,0 +1,299 @@
+use std::convert::Infallible;
+use std::time::SystemTime;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use embedded_graphics::mono_font::iso_8859_1::{
+    FONT_10X20, FONT_6X10, FONT_6X12, FONT_7X13, FONT_7X14, FONT_9X15, FONT_9X18,
+};
+use embedded_graphics::mono_font::{MonoFont, MonoTextStyle};
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, Size};
+use embedded_graphics::text::{Baseline, Text};
+use embedded_graphics::{Drawable, Pixel};
+use image::{Rgb, RgbImage, Rgba};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+use crate::filter::{FilterConfig, FilterFrame, FrameFilter, Verdict};
+
+/// Text rendered into a corner of the image.
+#[derive(clap::Args, Debug, Clone)]
+#[group(id = "overlay")]
+pub struct Args {
+    /// Text to render into the image, `strftime` sequences like `%Y-%m-%d %H:%M:%S` are replaced
+    #[arg(long = "overlay", value_name = "FORMAT", value_parser = parse_format)]
+    format: Option<String>,
+    /// Corner of the image to render the text into
+    #[arg(long, value_enum, default_value_t = Position::TopLeft)]
+    overlay_position: Position,
+    /// Height of the text in pixels
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    overlay_size: u32,
+    /// Color of the text as RRGGBB or RRGGBBAA
+    #[arg(long, default_value = "ffffff", value_parser = crate::parse_color)]
+    overlay_color: Rgba<u8>,
+    /// Color of the box behind the text as RRGGBB or RRGGBBAA
+    #[arg(long, default_value = "00000080", value_parser = crate::parse_color)]
+    overlay_background: Rgba<u8>,
+}
+
+impl Args {
+    pub fn filter(self) -> Option<FilterConfig> {
+        Some(FilterConfig::Overlay(Overlay {
+            format: self.format?,
+            position: self.overlay_position,
+            size: self.overlay_size,
+            color: self.overlay_color,
+            background: self.overlay_background,
+        }))
+    }
+}
+
+/// Text rendered into a corner of the image, see [`Args`].
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Overlay {
+    #[serde(deserialize_with = "deserialize_format")]
+    format: String,
+    #[serde(default = "default_position")]
+    position: Position,
+    #[serde(default = "default_size")]
+    size: u32,
+    #[serde(
+        default = "default_color",
+        deserialize_with = "crate::deserialize_color"
+    )]
+    color: Rgba<u8>,
+    #[serde(
+        default = "default_background",
+        deserialize_with = "crate::deserialize_color"
+    )]
+    background: Rgba<u8>,
+}
+
+#[derive(clap::ValueEnum, serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Position {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Position {
+    /// Top left coordinate of a box with the size `inner` in a box with the size `outer`.
+    pub fn place(self, outer: (u32, u32), inner: (u32, u32), margin: u32) -> (u32, u32) {
+        let right = outer.0.saturating_sub(inner.0 + margin);
+        let bottom = outer.1.saturating_sub(inner.1 + margin);
+        match self {
+            Position::TopLeft => (margin, margin),
+            Position::TopRight => (right, margin),
+            Position::BottomLeft => (margin, bottom),
+            Position::BottomRight => (right, bottom),
+        }
+    }
+}
+
+impl FrameFilter for Overlay {
+    fn name(&self) -> &'static str {
+        "overlay"
+    }
+
+    fn apply(&self, frame: &mut FilterFrame) -> anyhow::Result<Verdict> {
+        let received = frame.received;
+        self.draw(frame.image()?, received);
+        Ok(Verdict::Keep)
+    }
+}
+
+impl Overlay {
+    /// Renders the text for an image that was received at `time`.
+    fn draw(&self, image: &mut RgbImage, time: SystemTime) {
+        let text = DateTime::<Local>::from(time)
+            .format(&self.format)
+            .to_string();
+        let dimensions = image.dimensions();
+        draw_text(
+            image,
+            &text,
+            self.size,
+            (self.color, self.background),
+            |size, margin| self.position.place(dimensions, size, margin),
+        );
+    }
+}
+
+/// Renders `text` in a box with the colors `(foreground, background)`.
+///
+/// `place` gets the size of the box and the suggested margin, and returns the top left corner.
+pub fn draw_text(
+    image: &mut RgbImage,
+    text: &str,
+    size: u32,
+    (foreground, background): (Rgba<u8>, Rgba<u8>),
+    place: impl FnOnce((u32, u32), u32) -> (u32, u32),
+) {
+    let (font, scale) = select_font(size);
+    let mut canvas = Canvas::new(text, font);
+    let style = MonoTextStyle::new(font, BinaryColor::On);
+    for (idx, line) in text.lines().enumerate() {
+        let top = idx as i32 * font.character_size.height as i32;
+        let _ =
+            Text::with_baseline(line, Point::new(0, top), style, Baseline::Top).draw(&mut canvas);
+    }
+
+    let box_width = (canvas.width + 2 * PADDING) * scale;
+    let box_height = (canvas.height + 2 * PADDING) * scale;
+    let (left, top) = place((box_width, box_height), 2 * PADDING * scale);
+
+    for y in top..(top + box_height).min(image.height()) {
+        for x in left..(left + box_width).min(image.width()) {
+            let color = match canvas.get((x - left) / scale, (y - top) / scale) {
+                true => foreground,
+                false => background,
+            };
+            blend(image.get_pixel_mut(x, y), color);
+        }
+    }
+}
+
+/// Draws `src` over `dst`.
+pub fn blend(dst: &mut Rgb<u8>, src: Rgba<u8>) {
+    let Rgba([r, g, b, a]) = src;
+    let a = u16::from(a);
+    for (dst, src) in dst.0.iter_mut().zip([r, g, b]) {
+        *dst = ((u16::from(src) * a + u16::from(*dst) * (255 - a) + 127) / 255) as u8;
+    }
+}
+
+fn parse_format(format: &str) -> Result<String, String> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("Invalid format string {format:?}"));
+    }
+    Ok(format.to_owned())
+}
+
+fn deserialize_format<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    parse_format(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+fn default_position() -> Position {
+    Position::TopLeft
+}
+
+fn default_size() -> u32 {
+    20
+}
+
+fn default_color() -> Rgba<u8> {
+    Rgba([0xff, 0xff, 0xff, 0xff])
+}
+
+fn default_background() -> Rgba<u8> {
+    Rgba([0x00, 0x00, 0x00, 0x80])
+}
+
+/// Selects the font and the integer scaling factor that best matches `size`.
+fn select_font(size: u32) -> (&'static MonoFont<'static>, u32) {
+    const FONTS: [&MonoFont<'_>; 7] = [
+        &FONT_6X10,
+        &FONT_6X12,
+        &FONT_7X13,
+        &FONT_7X14,
+        &FONT_9X15,
+        &FONT_9X18,
+        &FONT_10X20,
+    ];
+    let largest = FONTS[FONTS.len() - 1].character_size.height;
+    let scale = ((size + largest / 2) / largest).max(1);
+    let font = FONTS
+        .iter()
+        .rev()
+        .find(|font| font.character_size.height * scale <= size)
+        .unwrap_or(&FONTS[0]);
+    (font, scale)
+}
+
+/// Unscaled space between the text and the border of its box.
+const PADDING: u32 = 2;
+
+/// A monochrome bitmap of the unscaled text.
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<bool>,
+}
+
+impl Canvas {
+    fn new(text: &str, font: &MonoFont<'_>) -> Self {
+        let columns = text.lines().map(|line| line.chars().count()).max();
+        let width = columns.unwrap_or(0) as u32 * font.character_size.width;
+        let height = text.lines().count() as u32 * font.character_size.height;
+        Self {
+            width,
+            height,
+            pixels: vec![false; (width * height) as usize],
+        }
+    }
+
+    /// Tests if the pixel is set, including a border of `PADDING` pixels.
+    fn get(&self, x: u32, y: u32) -> bool {
+        let (Some(x), Some(y)) = (x.checked_sub(PADDING), y.checked_sub(PADDING)) else {
+            return false;
+        };
+        x < self.width && y < self.height && self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+impl OriginDimensions for Canvas {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl DrawTarget for Canvas {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(Point { x, y }, color) in pixels {
+            if let (Ok(x), Ok(y)) = (u32::try_from(x), u32::try_from(y)) {
+                if x < self.width && y < self.height {
+                    self.pixels[(y * self.width + x) as usize] = color.is_on();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fonts() {
+        let select = |size| {
+            let (font, scale) = select_font(size);
+            let Size { width, height } = font.character_size;
+            (width, height, scale)
+        };
+        // too small, the smallest font is used
+        assert_eq!(select(1), (6, 10, 1));
+        assert_eq!(select(10), (6, 10, 1));
+        assert_eq!(select(12), (6, 12, 1));
+        assert_eq!(select(13), (7, 13, 1));
+        assert_eq!(select(15), (9, 15, 1));
+        assert_eq!(select(17), (9, 15, 1));
+        assert_eq!(select(20), (10, 20, 1));
+        assert_eq!(select(29), (10, 20, 1));
+        // larger sizes are scaled
+        assert_eq!(select(30), (9, 15, 2));
+        assert_eq!(select(40), (10, 20, 2));
+        assert_eq!(select(100), (10, 20, 5));
+    }
+}
@@ -0,0 +1,551 @@
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Datelike, Local, Timelike};
+use clap::ValueEnum;
+use http::{header, HeaderMap, HeaderName, HeaderValue};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+
+use crate::filter::{FilterFrame, FrameFilter, Verdict};
+use crate::mask::Mask;
+use crate::transform::{Flip, Rect, Rotation};
+
+/// A [Rhai](https://rhai.rs/) script that is called for every frame.
+///
+/// The script must define a function `filter()` without arguments. `this` is a map with the
+/// fields `received` (seconds since the epoch), `hour`, `minute`, `weekday` (1 = Monday, local
+/// time), `size` (bytes, if the image was not decoded by a previous filter), `width` and
+/// `height` (pixels), `headers` (the part headers), `tags` (headers to send along with the image)
+/// and `route` (name of the stream to publish the frame to instead, whose filters are applied,
+/// too). The frame is dropped if the function returns `false`. `headers`, `tags` and `route` can
+/// be modified. Tags must not be headers that affect the framing or the caching of the response,
+/// like `content-length`.
+///
+/// The image is changed by setting `crop` (`"WIDTHxHEIGHT+X+Y"`), `rotate` (`90`, `180` or `270`),
+/// `flip` (`"horizontal"`, `"vertical"` or `"both"`) and `mask` (`"SHAPE[/STYLE]"`, or an array
+/// of them), which are applied in this order like the filters of the same names.
+///
+/// ```rhai
+/// fn filter() {
+///     if this.hour < 6 { return false; }
+///     this.tags["x-camera"] = this.headers["x-camera-id"] ?? "unknown";
+///     if this.width > this.height { this.rotate = 90; }
+/// }
+/// ```
+///
+/// The script is reloaded when the file was modified.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    path: PathBuf,
+    /// Maximum number of operations per frame, to stop runaway scripts
+    #[serde(default = "default_max_operations")]
+    max_operations: NonZeroU64,
+}
+
+#[derive(Debug)]
+pub struct Script {
+    path: PathBuf,
+    engine: Engine,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    ast: Arc<AST>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Script {
+    /// Reads and compiles the script. Blocks.
+    pub fn load(config: Config) -> Result<Self, Error> {
+        let Config {
+            path,
+            max_operations,
+        } = config;
+
+        // Scripts can neither access files nor run for an unbounded time.
+        let mut engine = Engine::new();
+        let _ = engine
+            .set_module_resolver(DummyModuleResolver::new())
+            .set_max_operations(max_operations.get())
+            .set_max_call_levels(32)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(1 << 16)
+            .set_max_array_size(1 << 12)
+            .set_max_map_size(1 << 12)
+            .disable_symbol("eval");
+        let name = path.display().to_string();
+        let _ = engine.on_print(move |text| eprintln!("{name}: {text}"));
+
+        let (ast, modified) = compile(&engine, &path)?;
+        Ok(Self {
+            path,
+            engine,
+            state: Mutex::new(State {
+                ast: Arc::new(ast),
+                modified,
+                checked: Instant::now(),
+            }),
+        })
+    }
+
+    /// The compiled script, reloaded if the file was modified.
+    fn ast(&self) -> Arc<AST> {
+        let mut state = self.state.lock().unwrap();
+        if state.checked.elapsed() >= RELOAD_CHECK_INTERVAL {
+            state.checked = Instant::now();
+            let modified = modified(&self.path);
+            if modified != state.modified {
+                state.modified = modified;
+                match compile(&self.engine, &self.path) {
+                    Ok((ast, _)) => {
+                        eprintln!("Reloaded script {:?}", self.path);
+                        state.ast = Arc::new(ast);
+                    },
+                    // keep using the old script
+                    Err(err) => eprintln!("{:?}", anyhow::Error::new(err)),
+                }
+            }
+        }
+        Arc::clone(&state.ast)
+    }
+}
+
+impl FrameFilter for Script {
+    fn name(&self) -> &'static str {
+        "script"
+    }
+
+    fn apply(&self, frame: &mut FilterFrame) -> anyhow::Result<Verdict> {
+        let ast = self.ast();
+        let mut this = Dynamic::from_map(to_map(frame));
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut this);
+        let result: Dynamic = self
+            .engine
+            .call_fn_with_options(options, &mut Scope::new(), &ast, "filter", ())
+            .with_context(|| format!("Script {:?} failed", self.path))?;
+
+        let mut this = this
+            .try_cast::<Map>()
+            .ok_or_else(|| anyhow!("`this` must stay a map"))?;
+        frame.headers = to_header_map(this.remove("headers")).context("Invalid `headers`")?;
+        frame.tags = to_header_map(this.remove("tags"))
+            .and_then(check_tags)
+            .context("Invalid `tags`")?;
+        frame.route = take_string(&mut this, "route")?;
+        if let Ok(false) = result.as_bool() {
+            return Ok(Verdict::Drop);
+        }
+        for operation in operations(&mut this)? {
+            let _ = operation.apply(frame)?;
+        }
+        Ok(Verdict::Keep)
+    }
+}
+
+/// The changes of the image that the script asked for, in the order they are applied.
+fn operations(this: &mut Map) -> anyhow::Result<Vec<Box<dyn FrameFilter>>> {
+    let mut operations = Vec::<Box<dyn FrameFilter>>::new();
+    if let Some(crop) = take_string(this, "crop")? {
+        let rect = crop
+            .parse::<Rect>()
+            .map_err(|err| anyhow!("Invalid `crop`: {err}"))?;
+        operations.push(Box::new(rect));
+    }
+    match this.remove("rotate") {
+        Some(rotate) if !rotate.is_unit() => {
+            let degrees = rotate
+                .as_int()
+                .map_err(|ty| anyhow!("`rotate` must be an integer, not {ty}"))?;
+            let rotation = u16::try_from(degrees)
+                .map_err(|_| format!("Expected 90, 180 or 270, got {degrees}"))
+                .and_then(Rotation::try_from)
+                .map_err(|err| anyhow!("Invalid `rotate`: {err}"))?;
+            operations.push(Box::new(rotation));
+        },
+        _ => {},
+    }
+    if let Some(flip) = take_string(this, "flip")? {
+        let flip = <Flip as ValueEnum>::from_str(&flip, false)
+            .map_err(|err| anyhow!("Invalid `flip`: {err}"))?;
+        operations.push(Box::new(flip));
+    }
+    let masks = match this.remove("mask") {
+        Some(masks) if masks.is_array() => masks.cast::<Array>(),
+        Some(mask) if !mask.is_unit() => vec![mask],
+        _ => Vec::new(),
+    };
+    for mask in masks {
+        let mask = mask
+            .into_string()
+            .map_err(|ty| anyhow!("`mask` must be a string or an array of strings, not {ty}"))?;
+        let mask = mask
+            .parse::<Mask>()
+            .map_err(|err| anyhow!("Invalid `mask`: {err}"))?;
+        operations.push(Box::new(mask));
+    }
+    Ok(operations)
+}
+
+/// Removes an optional string from the map.
+fn take_string(this: &mut Map, name: &str) -> anyhow::Result<Option<String>> {
+    match this.remove(name) {
+        Some(value) if !value.is_unit() => {
+            Ok(Some(value.into_string().map_err(|ty| {
+                anyhow!("`{name}` must be a string, not {ty}")
+            })?))
+        },
+        _ => Ok(None),
+    }
+}
+
+fn to_map(frame: &FilterFrame) -> Map {
+    let received = frame
+        .received
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let local = DateTime::<Local>::from(frame.received);
+
+    let mut map = Map::new();
+    let mut insert = |name: &str, value| drop(map.insert(name.into(), value));
+    insert("received", Dynamic::from_float(received.as_secs_f64()));
+    insert("hour", Dynamic::from_int(local.hour().into()));
+    insert("minute", Dynamic::from_int(local.minute().into()));
+    insert(
+        "weekday",
+        Dynamic::from_int(local.weekday().number_from_monday().into()),
+    );
+    insert("size", match frame.jpeg() {
+        Some(jpeg) => Dynamic::from_int(jpeg.len() as i64),
+        None => Dynamic::UNIT,
+    });
+    let (width, height) = match frame.dimensions() {
+        Some((width, height)) => (
+            Dynamic::from_int(width.into()),
+            Dynamic::from_int(height.into()),
+        ),
+        None => (Dynamic::UNIT, Dynamic::UNIT),
+    };
+    insert("width", width);
+    insert("height", height);
+    insert(
+        "headers",
+        Dynamic::from_map(from_header_map(&frame.headers)),
+    );
+    insert("tags", Dynamic::from_map(from_header_map(&frame.tags)));
+    insert("route", match &frame.route {
+        Some(route) => route.clone().into(),
+        None => Dynamic::UNIT,
+    });
+    for operation in ["crop", "rotate", "flip", "mask"] {
+        insert(operation, Dynamic::UNIT);
+    }
+    map
+}
+
+/// Converts the headers into a map. Only the last value of repeated headers is kept.
+fn from_header_map(headers: &HeaderMap) -> Map {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            (name.as_str().into(), value.into())
+        })
+        .collect()
+}
+
+fn to_header_map(map: Option<Dynamic>) -> anyhow::Result<HeaderMap> {
+    let Some(map) = map else {
+        return Ok(HeaderMap::new());
+    };
+    let map = map
+        .try_cast::<Map>()
+        .ok_or_else(|| anyhow!("Expected a map"))?;
+    map.into_iter()
+        .map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name {name:?}"))?;
+            let value = HeaderValue::try_from(value.to_string())
+                .with_context(|| format!("Invalid value of header {name:?}"))?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Rejects tags that would change how the response or the multipart part is interpreted.
+fn check_tags(tags: HeaderMap) -> anyhow::Result<HeaderMap> {
+    if let Some(name) = tags.keys().find(|name| is_reserved(name)) {
+        bail!("Header {name:?} cannot be used as a tag");
+    }
+    Ok(tags)
+}
+
+fn is_reserved(name: &HeaderName) -> bool {
+    const RESERVED: &[HeaderName] = &[
+        header::AUTHORIZATION,
+        header::CACHE_CONTROL,
+        header::CONNECTION,
+        header::ETAG,
+        header::EXPIRES,
+        header::LAST_MODIFIED,
+        header::LOCATION,
+        header::PRAGMA,
+        header::SET_COOKIE,
+        header::STRICT_TRANSPORT_SECURITY,
+        header::TE,
+        header::TRAILER,
+        header::TRANSFER_ENCODING,
+        header::UPGRADE,
+        header::VARY,
+        header::WWW_AUTHENTICATE,
+    ];
+    const RESERVED_PREFIXES: &[&str] = &["access-control-", "content-", "proxy-"];
+    RESERVED.contains(name)
+        || name == "keep-alive"
+        || name == "set-cookie2"
+        || RESERVED_PREFIXES
+            .iter()
+            .any(|prefix| name.as_str().starts_with(prefix))
+}
+
+fn compile(engine: &Engine, path: &Path) -> Result<(AST, Option<SystemTime>), Error> {
+    let modified = modified(path);
+    let source = std::fs::read_to_string(path).map_err(|err| Error::Read(path.to_owned(), err))?;
+    let ast = engine
+        .compile(source)
+        .map_err(|err| Error::Compile(path.to_owned(), err))?;
+    if !ast
+        .iter_functions()
+        .any(|func| func.name == "filter" && func.params.is_empty())
+    {
+        return Err(Error::NoFilter(path.to_owned()));
+    }
+    Ok((ast, modified))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+fn default_max_operations() -> NonZeroU64 {
+    NonZeroU64::new(1_000_000).unwrap()
+}
+
+/// How often the modification time of the script is checked.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not read script {0:?}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Could not compile script {0:?}")]
+    Compile(PathBuf, #[source] rhai::ParseError),
+    #[error("Script {0:?} does not define a function `filter()`")]
+    NoFilter(PathBuf),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use bytes::Bytes;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    /// Compiles the script from a temporary file.
+    fn load(source: &str, max_operations: u64) -> Result<Script, Error> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "mjpeg-restream-test-{}-{}.rhai",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+        ));
+        std::fs::write(&path, source).unwrap();
+        let script = Script::load(Config {
+            path: path.clone(),
+            max_operations: NonZeroU64::new(max_operations).unwrap(),
+        });
+        std::fs::remove_file(&path).unwrap();
+        script
+    }
+
+    /// A white 64x48 image.
+    fn frame() -> FilterFrame {
+        let image = RgbImage::from_pixel(64, 48, Rgb([255, 255, 255]));
+        let mut data = Vec::new();
+        JpegEncoder::new(&mut data).encode_image(&image).unwrap();
+        FilterFrame::new(HeaderMap::new(), Bytes::from(data), SystemTime::now())
+    }
+
+    fn run(source: &str) -> anyhow::Result<(Verdict, FilterFrame)> {
+        let script = load(source, 100_000).unwrap();
+        let mut frame = frame();
+        let verdict = script.apply(&mut frame)?;
+        Ok((verdict, frame))
+    }
+
+    #[test]
+    fn tags() {
+        let mut tags = HeaderMap::new();
+        let _ = tags.insert("x-camera", HeaderValue::from_static("front"));
+        assert!(check_tags(tags).is_ok());
+        for name in [
+            "content-length",
+            "content-type",
+            "cache-control",
+            "etag",
+            "transfer-encoding",
+            "access-control-allow-origin",
+            "proxy-authenticate",
+            "keep-alive",
+            "set-cookie2",
+        ] {
+            let mut tags = HeaderMap::new();
+            let _ = tags.insert(HeaderName::from_static(name), HeaderValue::from_static("x"));
+            assert!(check_tags(tags).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn header_maps() {
+        assert!(to_header_map(None).unwrap().is_empty());
+        assert!(to_header_map(Some(Dynamic::UNIT)).is_err());
+        assert!(to_header_map(Some("x-a: 1".into())).is_err());
+
+        let mut map = Map::new();
+        let _ = map.insert("X-A".into(), "1".into());
+        let _ = map.insert("x-b".into(), Dynamic::from_int(2));
+        let headers = to_header_map(Some(Dynamic::from_map(map))).unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["x-a"], "1");
+        assert_eq!(headers["x-b"], "2");
+
+        for (name, value) in [("bad name", "1"), ("x-a", "line\nbreak")] {
+            let mut map = Map::new();
+            let _ = map.insert(name.into(), value.into());
+            assert!(to_header_map(Some(Dynamic::from_map(map))).is_err());
+        }
+    }
+
+    #[test]
+    fn filter() {
+        let (verdict, frame) = run(r#"
+            fn filter() {
+                this.tags["x-size"] = `${this.width}x${this.height}`;
+                this.route = "other";
+            }
+        "#)
+        .unwrap();
+        assert_eq!(verdict, Verdict::Keep);
+        assert_eq!(frame.tags["x-size"], "64x48");
+        assert_eq!(frame.route.as_deref(), Some("other"));
+        // unchanged
+        assert!(frame.jpeg().is_some());
+
+        let (verdict, _) = run("fn filter() { this.size < 0 }").unwrap();
+        assert_eq!(verdict, Verdict::Drop);
+
+        assert!(run(r#"fn filter() { this.tags["content-length"] = "1"; }"#).is_err());
+        assert!(run("fn filter() { this.route = 1; }").is_err());
+        assert!(run("fn filter() { this = 1; }").is_err());
+    }
+
+    #[test]
+    fn operations() {
+        let (verdict, mut frame) = run(r#"
+            fn filter() {
+                this.crop = "32x16+8+8";
+                this.rotate = 90;
+                this.flip = "horizontal";
+                this.mask = ["4x4+0+0", "4x4+12+28/fill=ff0000"];
+            }
+        "#)
+        .unwrap();
+        assert_eq!(verdict, Verdict::Keep);
+        let image = frame.image().unwrap();
+        assert_eq!(image.dimensions(), (16, 32));
+        assert!(image.get_pixel(1, 1).0.iter().all(|&value| value < 16));
+        assert!(image.get_pixel(8, 8).0.iter().all(|&value| value > 240));
+        let Rgb([r, g, b]) = *image.get_pixel(14, 30);
+        assert!(r > 240 && g < 16 && b < 16);
+
+        // without a mask, the image is not decoded
+        let (_, frame) = run(r#"fn filter() { this.rotate = 180; this.flip = "both"; }"#).unwrap();
+        assert_eq!(frame.dimensions(), Some((64, 48)));
+        assert!(frame.jpeg().is_some());
+
+        let (_, mut frame) = run(r#"fn filter() { this.mask = "0,0;10,0;0,10"; }"#).unwrap();
+        assert_eq!(frame.image().unwrap().get_pixel(1, 1).0, [0, 0, 0]);
+
+        // not applied to dropped frames
+        let (verdict, frame) = run("fn filter() { this.rotate = 45; false }").unwrap();
+        assert_eq!(verdict, Verdict::Drop);
+        assert!(frame.jpeg().is_some());
+
+        for operation in [
+            r#"this.crop = 32"#,
+            r#"this.crop = "32x16""#,
+            r#"this.rotate = 45"#,
+            r#"this.rotate = -90"#,
+            r#"this.rotate = "90""#,
+            r#"this.flip = "diagonal""#,
+            r#"this.mask = "4x4""#,
+            r#"this.mask = [1]"#,
+        ] {
+            let source = format!("fn filter() {{ {operation}; }}");
+            assert!(run(&source).is_err(), "{operation}");
+        }
+    }
+
+    #[test]
+    fn limits() {
+        assert!(matches!(
+            load("fn other() {}", 1000),
+            Err(Error::NoFilter(_)),
+        ));
+        assert!(matches!(
+            load(r#"fn filter() { eval("1") }"#, 1000),
+            Err(Error::Compile(..)),
+        ));
+        let nested = format!("fn filter() {{ {}1{} }}", "(".repeat(100), ")".repeat(100));
+        assert!(matches!(load(&nested, 1000), Err(Error::Compile(..))));
+
+        let mut frame = frame();
+        for source in [
+            "fn filter() { loop {} }",
+            "fn f(n) { f(n + 1) } fn filter() { f(0) }",
+            r#"fn filter() { let s = "x"; loop { s += s; } }"#,
+            "fn filter() { let a = []; loop { a.push(1); } }",
+            "fn filter() { let m = #{}; let i = 0; loop { m[`${i}`] = i; i += 1; } }",
+            r#"fn filter() { import "/etc/passwd" as m; }"#,
+        ] {
+            let script = load(source, 1_000_000).unwrap();
+            assert!(script.apply(&mut frame).is_err(), "{source}");
+        }
+        // the limit of operations is configurable
+        let script = load(
+            "fn filter() { let x = 0; for i in 0..1000 { x += i; } }",
+            100,
+        )
+        .unwrap();
+        assert!(script.apply(&mut frame).is_err());
+        let script = load(
+            "fn filter() { let x = 0; for i in 0..1000 { x += i; } }",
+            10_000,
+        )
+        .unwrap();
+        assert!(script.apply(&mut frame).is_ok());
+    }
+}
@@ -1,60 +1,1173 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::net::TcpListener;
+use std::net::{TcpListener, ToSocketAddrs};
+use std::num::NonZeroU64;
 use std::os::unix::net::UnixListener;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use actix_web::{get, App, HttpResponse, HttpServer};
+use actix_http::{HttpService, HttpServiceBuilder, Protocol, Request};
+use actix_server::ServerBuilder;
+use actix_service::boxed::{self, BoxServiceFactory};
+use actix_service::{map_config, IntoServiceFactory, ServiceFactoryExt};
+use actix_web::dev::{fn_service, AppConfig, Payload, Server, ServiceResponse};
+use actix_web::http::header::{
+    CacheControl, CacheDirective, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch,
+};
+use actix_web::http::StatusCode;
+use actix_web::rt::net::UnixStream;
+use actix_web::{get, routes, web, App, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use actix_ws::{Message, MessageStream, Session};
 use bytes::Bytes;
+use futures_util::future::{join_all, ready, Ready};
 use futures_util::StreamExt;
+use once_cell::sync::Lazy;
+use rustls::ServerConfig;
+use socket2::{Domain, Socket, Type};
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::task::spawn_blocking;
+use tokio::time::{sleep_until, timeout, Instant};
 
-use crate::image_holder;
+use crate::auth::{self, Auth, Denied, User};
+use crate::events::Event;
+use crate::frame::Frame;
+use crate::jpeg::Info;
+use crate::listener::ErrorCategory;
+use crate::metrics::metrics;
+use crate::source::{self, Source, State};
+use crate::systemd::{self, Inherited};
+use crate::tls::{self, CertResolver};
+use crate::uds::{self, SocketFile};
+use crate::variants::{Lease, NamedProfile, Profile};
+use crate::{fps_to_interval, millis_since_epoch, viewer, MAX_INTERVAL};
 
-pub async fn sender(addr: Args) -> Result<(), Error> {
-    let server = HttpServer::new(|| App::new().service(index).service(send_image));
-    let server = match (addr.tcp, addr.uds) {
-        (Some(addr), None) => {
-            let socket = spawn_blocking(|| TcpListener::bind(addr))
+pub async fn sender(
+    args: Args,
+    listeners: Vec<ListenerConfig>,
+    mut inherited: Vec<Inherited>,
+) -> Result<(), Error> {
+    // the entity tags contain the start time, not the time of the first request
+    let _ = Lazy::force(&RUN);
+    let mut profiles = args
+        .profiles
+        .into_iter()
+        .map(|NamedProfile { name, profile }| (name, profile))
+        .collect::<HashMap<_, _>>();
+    let _ = profiles
+        .entry("thumbnail".to_owned())
+        .or_insert(Profile::THUMBNAIL);
+    let config = web::Data::new(Config {
+        long_poll_timeout: Duration::from_secs(args.long_poll_timeout),
+        min_client_interval: args.max_client_fps.unwrap_or_default(),
+        keep_alive: (args.keep_alive > 0).then(|| Duration::from_secs(args.keep_alive)),
+        ready_max_age: Duration::from_secs(args.ready_max_age),
+        profiles,
+        max_ad_hoc_profiles: args.max_ad_hoc_profiles,
+    });
+
+    // the listeners given on the command line use the global TLS settings
+    let tls = args.tls.config();
+    let mut listeners = args
+        .listen
+        .tcp
+        .into_iter()
+        .map(|addr| ListenerConfig {
+            tcp: Some(addr),
+            uds: None,
+            fd_name: None,
+            tls: tls.clone(),
+            auth: None,
+            permissions: None,
+        })
+        .chain(args.listen.uds.into_iter().map(|path| ListenerConfig {
+            tcp: None,
+            uds: Some(path),
+            fd_name: None,
+            tls: None,
+            auth: None,
+            permissions: None,
+        }))
+        .chain(listeners)
+        .collect::<Vec<_>>();
+
+    // sockets passed by systemd go to the listener with the same `fd_name`, or else use the
+    // settings of the command line
+    let mut sockets = Vec::with_capacity(listeners.len());
+    for listener in &listeners {
+        let Some(name) = &listener.fd_name else {
+            sockets.push(Vec::new());
+            continue;
+        };
+        let (matching, rest) = inherited
+            .into_iter()
+            .partition(|socket| socket.name == *name);
+        inherited = rest;
+        if matching.is_empty() {
+            return Err(Error::Listener(format!(
+                "No socket named {name:?} was passed by systemd"
+            )));
+        }
+        sockets.push(matching);
+    }
+    for socket in inherited {
+        listeners.push(ListenerConfig {
+            tcp: None,
+            uds: None,
+            tls: match socket.socket {
+                systemd::Socket::Tcp(_) => tls.clone(),
+                systemd::Socket::Unix(_) => None,
+            },
+            fd_name: Some(socket.name.clone()),
+            auth: None,
+            permissions: None,
+        });
+        sockets.push(vec![socket]);
+    }
+    if listeners.is_empty() {
+        return Err(Error::NoListener);
+    }
+
+    let mut auths = Vec::with_capacity(listeners.len());
+    for listener in &listeners {
+        let auth = listener.auth.clone().unwrap_or_else(|| args.auth.clone());
+        let auth = spawn_blocking(move || Auth::load(&auth))
+            .await
+            .map_err(Error::JoinBlocking)?
+            .map_err(Error::Auth)?;
+        auths.push(web::Data::new(auth));
+    }
+    auth::check_access_lists(&auths).map_err(Error::Auth)?;
+
+    let addrs: Vec<String> = listeners.iter().filter_map(|l| l.tcp.clone()).collect();
+    let ipv4_ports = spawn_blocking(move || ipv4_ports(&addrs))
+        .await
+        .map_err(Error::JoinBlocking)?;
+    let ipv4_ports = Arc::new(ipv4_ports);
+
+    // one server, so that all listeners share the worker threads
+    let mut server = Server::build();
+    let mut resolvers = HashMap::new();
+    // the socket files are removed when this future completes or is dropped
+    let mut socket_files = Vec::new();
+    for ((mut listener, sockets), auth) in listeners.into_iter().zip(sockets).zip(auths) {
+        if listener.uds.is_some() && listener.permissions.is_none() {
+            listener.permissions = Some(args.listen.permissions.clone());
+        }
+        let socket_file;
+        (server, socket_file) = listen(
+            server,
+            listener,
+            sockets,
+            &ipv4_ports,
+            &mut resolvers,
+            Application {
+                config: config.clone(),
+                auth,
+            },
+        )
+        .await?;
+        socket_files.extend(socket_file);
+    }
+    drop(tokio::spawn(systemd::notify_ready(
+        args.notify_after_first_frame,
+    )));
+    server.run().await.map_err(Error::Run)?;
+    drop(socket_files);
+    Ok(())
+}
+
+/// Binds a listener and adds it to the `server`, with its own TLS and authentication settings.
+///
+/// `sockets` are the sockets passed by systemd for the listener's `fd_name`, see [`bind_tcp()`]
+/// for `ipv4_ports`. Listeners with the same certificate share the [`CertResolver`] in
+/// `resolvers`.
+async fn listen(
+    server: ServerBuilder,
+    listener: ListenerConfig,
+    sockets: Vec<Inherited>,
+    ipv4_ports: &Arc<HashSet<u16>>,
+    resolvers: &mut HashMap<tls::Config, ServerConfig>,
+    app: Application,
+) -> Result<(ServerBuilder, Option<SocketFile>), Error> {
+    match (
+        listener.tcp,
+        listener.uds,
+        listener.fd_name,
+        listener.tls,
+        listener.permissions,
+    ) {
+        (None, None, Some(name), tls, None) => {
+            let has_unix = sockets
+                .iter()
+                .any(|socket| matches!(socket.socket, systemd::Socket::Unix(_)));
+            if has_unix && tls.is_some() {
+                return Err(Error::Listener(
+                    "TLS is only supported on TCP listeners".to_owned(),
+                ));
+            }
+            let tls = load_tls(tls, resolvers).await?;
+            let server = sockets.into_iter().try_fold(server, |server, socket| {
+                match socket.socket {
+                    systemd::Socket::Tcp(socket) => app.tcp(server, &name, socket, &tls),
+                    systemd::Socket::Unix(socket) => app.uds(server, &name, socket),
+                }
+                .map_err(|err| Error::Inherited(name.clone(), err))
+            })?;
+            Ok((server, None))
+        },
+        (Some(addr), None, None, tls, None) => {
+            let tls = load_tls(tls, resolvers).await?;
+            let bind_addr = addr.clone();
+            let ipv4_ports = Arc::clone(ipv4_ports);
+            let sockets = spawn_blocking(move || bind_tcp(&bind_addr, &ipv4_ports))
                 .await
                 .map_err(Error::JoinBlocking)?
-                .map_err(Error::Tcp)?;
-            server.listen(socket).map_err(Error::Tcp)?
+                .map_err(|err| Error::Tcp(addr.clone(), err))?;
+            let server = sockets.into_iter().try_fold(server, |server, socket| {
+                app.tcp(server, &addr, socket, &tls)
+                    .map_err(|err| Error::Tcp(addr.clone(), err))
+            })?;
+            Ok((server, None))
         },
-        (None, Some(addr)) => {
-            let socket = spawn_blocking(|| UnixListener::bind(addr))
+        (None, Some(path), None, None, permissions) => {
+            let permissions = permissions.unwrap_or_default();
+            let bind_path = path.clone();
+            let (socket, socket_file) = spawn_blocking(move || uds::bind(&bind_path, &permissions))
                 .await
                 .map_err(Error::JoinBlocking)?
-                .map_err(Error::Uds)?;
-            server.listen_uds(socket).map_err(Error::Uds)?
+                .map_err(Error::Socket)?;
+            let server = app
+                .uds(server, &path.to_string_lossy(), socket)
+                .map_err(|err| Error::Uds(path, err))?;
+            Ok((server, Some(socket_file)))
         },
-        _ => unreachable!(),
+        (None, Some(_), None, Some(_), _) => Err(Error::Listener(
+            "TLS is only supported on TCP listeners".to_owned(),
+        )),
+        (Some(_), None, None, _, Some(_)) => Err(Error::Listener(
+            "Permissions are only supported on Unix sockets".to_owned(),
+        )),
+        (None, None, Some(_), _, Some(_)) => Err(Error::Listener(
+            "Permissions cannot be changed on sockets passed by systemd".to_owned(),
+        )),
+        _ => Err(Error::Listener(
+            "Exactly one of `tcp`, `uds` and `fd_name` must be given".to_owned(),
+        )),
+    }
+}
+
+/// The application of a listener, what [`HttpServer`](actix_web::HttpServer) would set up for
+/// every socket.
+#[derive(Debug, Clone)]
+struct Application {
+    config: web::Data<Config>,
+    auth: web::Data<Auth>,
+}
+
+type AppFactory = BoxServiceFactory<(), Request, ServiceResponse, HttpResponse, ()>;
+
+impl Application {
+    fn tcp(
+        &self,
+        server: ServerBuilder,
+        name: &str,
+        socket: TcpListener,
+        tls: &Option<ServerConfig>,
+    ) -> std::io::Result<ServerBuilder> {
+        let this = self.clone();
+        match tls.clone() {
+            Some(tls) => server.listen(name, socket, move || {
+                this.http().finish(this.factory()).rustls_021(tls.clone())
+            }),
+            None => server.listen(name, socket, move || {
+                this.http().finish(this.factory()).tcp()
+            }),
+        }
+    }
+
+    fn uds(
+        &self,
+        server: ServerBuilder,
+        name: &str,
+        socket: UnixListener,
+    ) -> std::io::Result<ServerBuilder> {
+        let this = self.clone();
+        server.listen_uds(name, socket, move || {
+            fn_service(|io: UnixStream| ready(Ok((io, Protocol::Http1, None))))
+                .and_then(this.http().finish(this.factory()))
+        })
+    }
+
+    fn http<T>(&self) -> HttpServiceBuilder<T, AppFactory> {
+        // the default of `HttpServer`, but not of `HttpService`
+        HttpService::build().client_disconnect_timeout(Duration::from_secs(1))
+    }
+
+    fn factory(&self) -> AppFactory {
+        let app = App::new()
+            .app_data(self.config.clone())
+            .app_data(self.auth.clone())
+            .configure(routes)
+            .into_factory()
+            .map_err(|err| err.error_response());
+        // `AppConfig` cannot be built with the address and scheme of the listener outside of
+        // actix-web, but only `ConnectionInfo` and URL generation use them, not the routes
+        boxed::factory(map_config(app, |()| AppConfig::default()))
+    }
+}
+
+/// Loads the certificate and watches it for changes, if TLS is enabled.
+///
+/// A certificate is loaded and watched only once, even if several listeners use it.
+async fn load_tls(
+    tls: Option<tls::Config>,
+    resolvers: &mut HashMap<tls::Config, ServerConfig>,
+) -> Result<Option<ServerConfig>, Error> {
+    let Some(tls) = tls else {
+        return Ok(None);
     };
-    server.run().await.map_err(Error::Run)
+    if let Some(config) = resolvers.get(&tls) {
+        return Ok(Some(config.clone()));
+    }
+    let config = tls.clone();
+    let resolver = spawn_blocking(|| CertResolver::load(config))
+        .await
+        .map_err(Error::JoinBlocking)?
+        .map_err(Error::Tls)?;
+    let resolver = Arc::new(resolver);
+    drop(tokio::spawn(Arc::clone(&resolver).watch()));
+    let config = resolver.server_config();
+    let _ = resolvers.insert(tls, config.clone());
+    Ok(Some(config))
 }
 
-#[get("/")]
-async fn index() -> HttpResponse {
-    HttpResponse::TemporaryRedirect()
-        .append_header((http::header::LOCATION, "/image.jpeg"))
-        .content_type(mime::TEXT_PLAIN_UTF_8)
-        .body("-> /image.jpeg\n")
+fn routes(cfg: &mut web::ServiceConfig) {
+    let _ = cfg
+        .service(viewer::index)
+        .service(viewer::login)
+        .service(viewer::view)
+        .service(viewer::wall)
+        .service(viewer::script)
+        .service(viewer::style)
+        .service(send_streams)
+        .service(send_stream)
+        .service(send_image)
+        .service(send_websocket)
+        .service(send_events)
+        .service(send_metrics)
+        .service(send_health)
+        .service(send_readiness)
+        .service(send_snapshot)
+        .service(send_next)
+        .service(send_filter_stats);
+}
+
+/// Binds every address `addr` resolves to, e.g. `127.0.0.1` and `::1` for `localhost`. Blocks.
+///
+/// IPv6 sockets keep the system's default, so that e.g. `[::]:8000` usually accepts IPv4
+/// connections, too. Only if one of the `ipv4_ports` is bound on an IPv4 address, too, the IPv6
+/// socket is restricted to IPv6, so that e.g. `0.0.0.0:8000` and `[::]:8000` can be bound at the
+/// same time.
+fn bind_tcp(addr: &str, ipv4_ports: &HashSet<u16>) -> std::io::Result<Vec<TcpListener>> {
+    addr.to_socket_addrs()?
+        .map(|addr| {
+            let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+            if addr.is_ipv6() && ipv4_ports.contains(&addr.port()) {
+                socket.set_only_v6(true)?;
+            }
+            socket.set_reuse_address(true)?;
+            socket.bind(&addr.into())?;
+            socket.listen(TCP_BACKLOG)?;
+            Ok(socket.into())
+        })
+        .collect()
+}
+
+/// The ports of all IPv4 addresses the `--tcp` addresses resolve to. Blocks.
+///
+/// Addresses that cannot be resolved are skipped, [`bind_tcp()`] reports the error.
+fn ipv4_ports(addrs: &[String]) -> HashSet<u16> {
+    addrs
+        .iter()
+        .filter_map(|addr| addr.to_socket_addrs().ok())
+        .flatten()
+        .filter(|addr| addr.is_ipv4())
+        .map(|addr| addr.port())
+        .collect()
+}
+
+/// Maximum number of pending TCP connections, the same as actix-web's default.
+const TCP_BACKLOG: i32 = 1024;
+
+#[derive(Debug, serde::Deserialize)]
+struct ImageQuery {
+    /// Maximum number of images per second
+    fps: Option<f64>,
+    /// Minimum number of milliseconds between two images
+    interval: Option<u64>,
+}
+
+impl ImageQuery {
+    /// The minimum time between two images sent to the client.
+    fn interval(&self, config: &Config) -> Result<Duration, String> {
+        let interval = match (self.fps, self.interval) {
+            (Some(fps), _) => fps_to_interval(fps)?,
+            (None, Some(interval)) => Some(Duration::from_millis(interval))
+                .filter(|&interval| interval <= MAX_INTERVAL)
+                .ok_or_else(|| format!("Interval must be at most one day, not {interval} ms"))?,
+            (None, None) => Duration::ZERO,
+        };
+        Ok(interval.max(config.min_client_interval))
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ProfileQuery {
+    /// Name of a profile given with `--profile`
+    profile: Option<String>,
+    /// Maximum width of the image
+    width: Option<u32>,
+    /// Maximum height of the image
+    height: Option<u32>,
+    /// JPEG quality
+    quality: Option<u8>,
+}
+
+impl ProfileQuery {
+    /// The requested profile, leased until the response is complete. Profiles that are not named
+    /// are limited per source, because each one has to be transcoded separately.
+    fn resolve(
+        &self,
+        config: &Config,
+        source: &'static Source,
+    ) -> Result<Option<Lease<'static>>, String> {
+        let profile = if let Some(name) = &self.profile {
+            match config.profiles.get(name) {
+                Some(profile) => *profile,
+                None => return Err(format!("Unknown profile {name:?}")),
+            }
+        } else {
+            match (self.width, self.height, self.quality) {
+                (None, None, None) => return Ok(None),
+                (width, height, quality) => Profile::new(width, height, quality)?,
+            }
+        };
+        let named = config.profiles.values().any(|named| *named == profile);
+        match source
+            .variants
+            .lease(profile, named, config.max_ad_hoc_profiles)
+        {
+            Some(lease) => Ok(Some(lease)),
+            None => {
+                Err("Too many different image sizes are in use, use a named profile".to_owned())
+            },
+        }
+    }
 }
 
+#[routes]
 #[get("/image.jpeg")]
-async fn send_image() -> HttpResponse {
+#[get("/streams/{name}/image.jpeg")]
+async fn send_image(
+    Stream(source): Stream,
+    query: web::Query<ImageQuery>,
+    profile: web::Query<ProfileQuery>,
+    config: web::Data<Config>,
+) -> HttpResponse {
+    let interval = match query.interval(&config) {
+        Ok(interval) => interval,
+        Err(err) => return bad_request(err),
+    };
+    let profile = match profile.resolve(&config, source) {
+        Ok(profile) => profile,
+        Err(err) => return bad_request(err),
+    };
+
     HttpResponse::Ok()
         .append_header((
             http::header::CONTENT_TYPE,
             "multipart/x-mixed-replace; boundary=--frameboundary",
         ))
         .streaming(async_stream::stream! {
-            let mut updates = std::pin::pin!(image_holder().stream_updates());
-            while let Some(bytes) = updates.next().await {
-                yield Ok::<Bytes, NoError>(bytes);
+            let _client = source.client();
+            let mut updates = std::pin::pin!(source.holder.stream_updates());
+            let mut last_part: Option<Bytes> = None;
+            loop {
+                let update = match config.keep_alive {
+                    Some(keep_alive) => match timeout(keep_alive, updates.next()).await {
+                        Ok(update) => update,
+                        Err(_) => {
+                            // Re-send the latest image, so that proxies and browsers don't close
+                            // the idle connection. The sequence number stays the same.
+                            if let Some(part) = &last_part {
+                                metrics()
+                                    .bytes_sent
+                                    .with_label_values(&[&source.name, "image"])
+                                    .inc_by(part.len() as u64);
+                                yield Ok(Bytes::clone(part));
+                            }
+                            continue;
+                        },
+                    },
+                    None => updates.next().await,
+                };
+                let Some((seq, frame)) = update else {
+                    break;
+                };
+                let frame = match &profile {
+                    Some(profile) => match profile.get(seq, &frame).await {
+                        Ok(frame) => frame,
+                        Err(err) => {
+                            eprintln!("{err:?}");
+                            continue;
+                        },
+                    },
+                    None => frame,
+                };
+                let sent = Instant::now();
+                last_part = Some(frame.part.clone());
+                metrics().sent(source, "image", &frame, frame.part.len());
+                yield Ok::<Bytes, NoError>(frame.part);
+                // images that arrive in the meantime are skipped for this client
+                sleep_until(sent + interval).await;
+            }
+        })
+}
+
+/// Sends every image as a binary message, preceded by a text message with its metadata.
+#[routes]
+#[get("/ws")]
+#[get("/streams/{name}/ws")]
+async fn send_websocket(
+    req: HttpRequest,
+    Stream(source): Stream,
+    body: web::Payload,
+    query: web::Query<ImageQuery>,
+    profile: web::Query<ProfileQuery>,
+    config: web::Data<Config>,
+) -> HttpResponse {
+    let interval = match query.interval(&config) {
+        Ok(interval) => interval,
+        Err(err) => return bad_request(err),
+    };
+    let profile = match profile.resolve(&config, source) {
+        Ok(profile) => profile,
+        Err(err) => return bad_request(err),
+    };
+    let (resp, session, messages) = match actix_ws::handle(&req, body) {
+        Ok(result) => result,
+        Err(err) => return err.error_response(),
+    };
+    drop(actix_web::rt::spawn(websocket_session(
+        source,
+        session,
+        messages,
+        interval,
+        profile,
+        config.keep_alive,
+    )));
+    resp
+}
+
+#[derive(Debug, serde::Serialize)]
+struct FrameInfo {
+    sequence: u64,
+    /// Milliseconds since the epoch when the image was received
+    timestamp: u64,
+    width: Option<u16>,
+    height: Option<u16>,
+    /// Size of the following binary message
+    size: usize,
+}
+
+async fn websocket_session(
+    source: &'static Source,
+    mut session: Session,
+    mut messages: MessageStream,
+    interval: Duration,
+    profile: Option<Lease<'static>>,
+    keep_alive: Option<Duration>,
+) {
+    let _client = source.client();
+    let mut updates = std::pin::pin!(source.holder.stream_updates());
+    let mut next_due = Instant::now();
+    let mut last_sent = Instant::now();
+    loop {
+        let idle = async {
+            match keep_alive {
+                Some(keep_alive) => sleep_until(last_sent + keep_alive).await,
+                None => std::future::pending().await,
+            }
+        };
+        let update = async {
+            // images that arrive in the meantime are skipped for this client
+            sleep_until(next_due).await;
+            updates.next().await
+        };
+        select! {
+            message = messages.recv() => match message {
+                Some(Ok(Message::Ping(data))) => {
+                    if session.pong(&data).await.is_err() {
+                        return;
+                    }
+                },
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {},
+            },
+            () = idle => {
+                if session.ping(b"").await.is_err() {
+                    return;
+                }
+                last_sent = Instant::now();
+            },
+            Some((seq, frame)) = update => {
+                let frame = match &profile {
+                    Some(profile) => match profile.get(seq, &frame).await {
+                        Ok(frame) => frame,
+                        Err(err) => {
+                            eprintln!("{err:?}");
+                            continue;
+                        },
+                    },
+                    None => frame,
+                };
+                let jpeg = Info::parse(&frame.body);
+                let info = FrameInfo {
+                    sequence: seq.get(),
+                    timestamp: millis_since_epoch(frame.received),
+                    width: jpeg.map(|jpeg| jpeg.width),
+                    height: jpeg.map(|jpeg| jpeg.height),
+                    size: frame.body.len(),
+                };
+                let info = serde_json::to_string(&info).unwrap();
+                next_due = Instant::now() + interval;
+                last_sent = Instant::now();
+                // The session only buffers a few messages, so sending waits for slow clients.
+                metrics().sent(source, "websocket", &frame, frame.body.len());
+                if session.text(info).await.is_err() || session.binary(frame.body).await.is_err() {
+                    return;
+                }
+            },
+        }
+    }
+    let _ = session.close(None).await;
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct EventsQuery {
+    /// Only send every n-th `frame` event, 0 to send none
+    frames: Option<u64>,
+}
+
+/// Sends server-sent events about the state of the source, see [`Event`].
+#[routes]
+#[get("/events")]
+#[get("/streams/{name}/events")]
+async fn send_events(
+    Stream(source): Stream,
+    query: web::Query<EventsQuery>,
+    config: web::Data<Config>,
+) -> HttpResponse {
+    let frames = query.frames.unwrap_or(1);
+    let keep_alive = config.keep_alive;
+
+    // subscribe before reading the current state, so that no transition gets lost
+    let mut events = source.subscribe();
+    let status = source.status();
+    let mut initial = vec![
+        Event::State {
+            state: status.state,
+        },
+        Event::Clients {
+            count: source.clients(),
+        },
+    ];
+    if let Some(info) = status.resolution {
+        initial.push(Event::Resolution {
+            width: info.width,
+            height: info.height,
+        });
+    }
+
+    HttpResponse::Ok()
+        .content_type(mime::TEXT_EVENT_STREAM)
+        .append_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(async_stream::stream! {
+            for event in initial {
+                yield Ok::<Bytes, NoError>(event.to_sse());
+            }
+            let mut frame_count = 0;
+            loop {
+                let event = match keep_alive {
+                    Some(keep_alive) => match timeout(keep_alive, events.recv()).await {
+                        Ok(event) => event,
+                        Err(_) => {
+                            yield Ok(Bytes::from_static(b": keep-alive\n\n"));
+                            continue;
+                        },
+                    },
+                    None => events.recv().await,
+                };
+                let event = match event {
+                    Ok(event) => event,
+                    // the client is too slow, some events were skipped
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if let Event::Frame { .. } = event {
+                    let send = frames > 0 && frame_count % frames == 0;
+                    frame_count += 1;
+                    if !send {
+                        continue;
+                    }
+                }
+                yield Ok(event.to_sse());
             }
         })
 }
 
+/// The latest image. While the source is offline, the response is `503 Service Unavailable`
+/// instead of the placeholder that the streams show.
+#[routes]
+#[get("/snapshot.jpeg")]
+#[get("/streams/{name}/snapshot.jpeg")]
+async fn send_snapshot(
+    req: HttpRequest,
+    Stream(source): Stream,
+    profile: web::Query<ProfileQuery>,
+    config: web::Data<Config>,
+) -> HttpResponse {
+    let profile = match profile.resolve(&config, source) {
+        Ok(profile) => profile,
+        Err(err) => return bad_request(err),
+    };
+    let Some((seq, frame)) = source.holder.get().await else {
+        return no_image_response();
+    };
+    if frame.placeholder {
+        return HttpResponse::ServiceUnavailable()
+            .append_header((http::header::RETRY_AFTER, "1"))
+            .content_type(mime::TEXT_PLAIN_UTF_8)
+            .body("The source is offline\n");
+    }
+
+    let last_modified = truncate_to_secs(frame.received);
+    // If-None-Match takes precedence over If-Modified-Since, RFC 9110, 13.1.3
+    let not_modified = match if_none_match(&req, &etag(seq)) {
+        Some(matches) => matches,
+        None => match IfModifiedSince::parse(&req) {
+            Ok(IfModifiedSince(since)) => HttpDate::from(last_modified) <= since,
+            Err(_) => false,
+        },
+    };
+    image_response(source, "snapshot", seq, frame, not_modified, profile).await
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct NextQuery {
+    /// Sequence number of the last image the client has seen
+    after: Option<u64>,
+    /// Number of seconds to wait for a new image
+    wait: Option<u64>,
+}
+
+/// Waits until an image newer than `?after=` or `If-None-Match` is available.
+///
+/// If none arrives in time, the response is `304 Not Modified` if the request had a matching
+/// `If-None-Match`, and `204 No Content` otherwise.
+#[routes]
+#[get("/next.jpeg")]
+#[get("/streams/{name}/next.jpeg")]
+async fn send_next(
+    req: HttpRequest,
+    Stream(source): Stream,
+    query: web::Query<NextQuery>,
+    profile: web::Query<ProfileQuery>,
+    config: web::Data<Config>,
+) -> HttpResponse {
+    let profile = match profile.resolve(&config, source) {
+        Ok(profile) => profile,
+        Err(err) => return bad_request(err),
+    };
+    let after = match query.after {
+        Some(after) => after,
+        None => match IfNoneMatch::parse(&req) {
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().filter_map(parse_etag).max().unwrap_or(0),
+            Ok(IfNoneMatch::Any) | Err(_) => 0,
+        },
+    };
+    let wait = query
+        .wait
+        .map_or(config.long_poll_timeout, Duration::from_secs)
+        .min(config.long_poll_timeout);
+
+    if let Some((seq, frame)) = source.holder.get().await {
+        if seq.get() < after {
+            // The client saw a sequence number of a previous run of this program.
+            return image_response(source, "next", seq, frame, false, profile).await;
+        }
+    }
+    match timeout(wait, source.holder.get_newer(after)).await {
+        Ok((seq, frame)) => image_response(source, "next", seq, frame, false, profile).await,
+        Err(_) => match source.holder.get().await {
+            Some((seq, frame)) if if_none_match(&req, &etag(seq)) == Some(true) => {
+                image_response(source, "next", seq, frame, true, profile).await
+            },
+            // only conditional requests may be answered with 304, RFC 9110, 15.4.5
+            Some(_) => HttpResponse::NoContent()
+                .append_header(CacheControl(vec![CacheDirective::NoCache]))
+                .finish(),
+            None => no_image_response(),
+        },
+    }
+}
+
+/// Whether `If-None-Match` lists the entity tag, `None` if the request has no `If-None-Match`.
+fn if_none_match(req: &HttpRequest, etag: &EntityTag) -> Option<bool> {
+    if !req.headers().contains_key(http::header::IF_NONE_MATCH) {
+        return None;
+    }
+    Some(match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        Err(_) => false,
+    })
+}
+
+async fn image_response(
+    source: &Source,
+    endpoint: &str,
+    seq: NonZeroU64,
+    frame: Frame,
+    not_modified: bool,
+    profile: Option<Lease<'_>>,
+) -> HttpResponse {
+    let frame = match profile {
+        Some(profile) if !not_modified => match profile.get(seq, &frame).await {
+            Ok(frame) => frame,
+            Err(err) => {
+                return HttpResponse::InternalServerError()
+                    .content_type(mime::TEXT_PLAIN_UTF_8)
+                    .body(format!("{err}\n"));
+            },
+        },
+        _ => frame,
+    };
+
+    let mut resp = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    let resp = resp
+        .append_header((http::header::ETAG, etag(seq)))
+        .append_header((
+            http::header::LAST_MODIFIED,
+            HttpDate::from(truncate_to_secs(frame.received)),
+        ))
+        .append_header(CacheControl(vec![CacheDirective::NoCache]));
+    let resp = frame
+        .tags
+        .iter()
+        .fold(resp, |resp, tag| resp.append_header(tag));
+    if not_modified {
+        resp.finish()
+    } else {
+        metrics().sent(source, endpoint, &frame, frame.body.len());
+        resp.content_type(mime::IMAGE_JPEG).body(frame.body)
+    }
+}
+
+/// The entity tag of an image, `"{run}-{sequence}"`.
+///
+/// The sequence numbers restart on every start of the program, so the tags contain the start
+/// time, too. Otherwise a cache would consider an image of a previous run to be up to date.
+fn etag(seq: NonZeroU64) -> EntityTag {
+    EntityTag::new_strong(format!("{:x}-{seq}", *RUN))
+}
+
+/// The sequence number of an entity tag of this run.
+fn parse_etag(tag: &EntityTag) -> Option<u64> {
+    let (run, seq) = tag.tag().split_once('-')?;
+    if u64::from_str_radix(run, 16).ok()? != *RUN {
+        return None;
+    }
+    seq.parse().ok()
+}
+
+/// Milliseconds since the epoch when the program was started.
+static RUN: Lazy<u64> = Lazy::new(|| millis_since_epoch(SystemTime::now()));
+
+#[derive(Debug, serde::Serialize)]
+struct StreamInfo {
+    name: &'static str,
+    /// URL of the upstream, without credentials and with the values of query parameters
+    /// redacted, as they often contain tokens
+    url: String,
+    state: State,
+    /// Milliseconds since the epoch when the source started or stopped streaming
+    since: u64,
+    last_error: Option<ErrorCategory>,
+    reconnects: u64,
+    /// Images per second received from the upstream
+    fps: f64,
+    /// Bits per second received from the upstream
+    bitrate: f64,
+    last_frame: Option<LastFrame>,
+    /// Clients that currently stream images
+    viewers: usize,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct LastFrame {
+    sequence: u64,
+    /// Milliseconds since the epoch when the image was received
+    received: u64,
+    size: usize,
+    width: Option<u16>,
+    height: Option<u16>,
+    subsampling: Option<&'static str>,
+}
+
+impl StreamInfo {
+    async fn new(source: &'static Source) -> Self {
+        let status = source.status();
+        let (fps, byte_rate) = source.rate();
+        let last_frame = source.holder.get().await.map(|(seq, frame)| {
+            let info = Info::parse(&frame.body);
+            LastFrame {
+                sequence: seq.get(),
+                received: millis_since_epoch(frame.received),
+                size: frame.body.len(),
+                width: info.map(|info| info.width),
+                height: info.map(|info| info.height),
+                subsampling: info.and_then(|info| info.subsampling),
+            }
+        });
+
+        let mut url = source.url.clone();
+        if !url.username().is_empty() || url.password().is_some() {
+            // only fails for URLs that cannot have credentials
+            let _ = url.set_username("redacted");
+            let _ = url.set_password(Some("redacted"));
+        }
+        if url.query().is_some() {
+            let names: Vec<String> = url.query_pairs().map(|(name, _)| name.into()).collect();
+            let _ = url
+                .query_pairs_mut()
+                .clear()
+                .extend_pairs(names.iter().map(|name| (name, "redacted")));
+        }
+        url.set_fragment(None);
+
+        Self {
+            name: &source.name,
+            url: url.into(),
+            state: status.state,
+            since: millis_since_epoch(status.since),
+            last_error: status.last_error,
+            reconnects: status.reconnects,
+            fps,
+            bitrate: 8.0 * byte_rate,
+            last_frame,
+            viewers: source.clients(),
+        }
+    }
+}
+
+/// All streams the client may view, the first one is the default stream.
+#[get("/api/streams")]
+async fn send_streams(user: User) -> HttpResponse {
+    let sources = source::sources()
+        .iter()
+        .filter(|source| user.authorize(source).is_ok());
+    let streams = join_all(sources.map(StreamInfo::new)).await;
+    HttpResponse::Ok()
+        .append_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(streams)
+}
+
+#[get("/api/streams/{name}")]
+async fn send_stream(Stream(source): Stream) -> HttpResponse {
+    HttpResponse::Ok()
+        .append_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(StreamInfo::new(source).await)
+}
+
+#[derive(Debug, serde::Serialize)]
+struct FilterStats {
+    name: &'static str,
+    runs: u64,
+    errors: u64,
+    total_seconds: f64,
+    max_seconds: f64,
+}
+
+/// Timing information of the filters of a source, in the order they are applied.
+#[get("/api/streams/{name}/filters")]
+async fn send_filter_stats(Stream(source): Stream) -> HttpResponse {
+    let stats = source
+        .chain
+        .stats()
+        .map(|(name, stats)| FilterStats {
+            name,
+            runs: stats.runs(),
+            errors: stats.errors(),
+            total_seconds: stats.total_seconds(),
+            max_seconds: stats.max_seconds(),
+        })
+        .collect::<Vec<_>>();
+    HttpResponse::Ok()
+        .append_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(stats)
+}
+
+#[get("/metrics")]
+async fn send_metrics(user: User) -> Result<HttpResponse, Denied> {
+    user.require()?;
+    let (content_type, data) = metrics().encode();
+    Ok(HttpResponse::Ok()
+        .append_header((http::header::CONTENT_TYPE, content_type))
+        .body(data))
+}
+
+/// The process is alive.
+#[get("/healthz")]
+async fn send_health() -> HttpResponse {
+    HttpResponse::Ok()
+        .append_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(serde_json::json!({ "status": "ok" }))
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Readiness {
+    ready: bool,
+    sources: Vec<SourceReadiness>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct SourceReadiness {
+    name: &'static str,
+    required: bool,
+    ready: bool,
+    state: State,
+    /// Seconds since the last image was received
+    last_frame_age: Option<f64>,
+    last_error: Option<ErrorCategory>,
+}
+
+/// Every required source received an image recently.
+///
+/// The aggregate covers all sources, but only those the client may view are listed.
+#[get("/readyz")]
+async fn send_readiness(config: web::Data<Config>, user: User) -> HttpResponse {
+    let now = SystemTime::now();
+    let sources = source::sources()
+        .iter()
+        .map(|source| {
+            let status = source.status();
+            let age = status
+                .last_part
+                .map(|time| now.duration_since(time).unwrap_or_default());
+            SourceReadiness {
+                name: &source.name,
+                required: source.required,
+                ready: age.is_some_and(|age| age <= config.ready_max_age),
+                state: status.state,
+                last_frame_age: age.map(|age| age.as_secs_f64()),
+                last_error: status.last_error,
+            }
+        })
+        .collect::<Vec<_>>();
+    let ready = sources
+        .iter()
+        .all(|source| source.ready || !source.required);
+    let sources = source::sources()
+        .iter()
+        .zip(sources)
+        .filter(|(source, _)| user.authorize(source).is_ok())
+        .map(|(_, readiness)| readiness)
+        .collect();
+
+    let mut resp = if ready {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    resp.append_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(Readiness { ready, sources })
+}
+
+/// The source named in the path, or the default source, if the client may view it.
+struct Stream(&'static Source);
+
+impl FromRequest for Stream {
+    type Error = StreamError;
+    type Future = Ready<Result<Self, StreamError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Self::extract(req))
+    }
+}
+
+impl Stream {
+    fn extract(req: &HttpRequest) -> Result<Self, StreamError> {
+        let user = User::extract(req)?;
+        let name = req.match_info().get("name");
+        let source = source::find(name)
+            .ok_or_else(|| StreamError::Unknown(name.unwrap_or_default().to_owned()))?;
+        user.authorize(source)?;
+        Ok(Self(source))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum StreamError {
+    #[error("Unknown stream {0:?}")]
+    Unknown(String),
+    #[error(transparent)]
+    Denied(#[from] Denied),
+}
+
+impl ResponseError for StreamError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            StreamError::Unknown(_) => StatusCode::NOT_FOUND,
+            StreamError::Denied(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            StreamError::Unknown(_) => HttpResponse::NotFound()
+                .content_type(mime::TEXT_PLAIN_UTF_8)
+                .body(format!("{self}\n")),
+            StreamError::Denied(err) => err.error_response(),
+        }
+    }
+}
+
+fn bad_request(err: String) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type(mime::TEXT_PLAIN_UTF_8)
+        .body(format!("{err}\n"))
+}
+
+fn no_image_response() -> HttpResponse {
+    HttpResponse::ServiceUnavailable()
+        .append_header((http::header::RETRY_AFTER, "1"))
+        .content_type(mime::TEXT_PLAIN_UTF_8)
+        .body("No image received yet\n")
+}
+
+/// HTTP dates have a resolution of one second.
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
 #[derive(Debug, Clone, Copy)]
 enum NoError {}
 
@@ -66,25 +1179,183 @@ impl fmt::Display for NoError {
     }
 }
 
+#[derive(Debug)]
+struct Config {
+    long_poll_timeout: Duration,
+    min_client_interval: Duration,
+    /// Idle time after which `/image.jpeg` re-sends the latest image
+    keep_alive: Option<Duration>,
+    /// Maximum age of the last image of a required source for `/readyz`
+    ready_max_age: Duration,
+    profiles: HashMap<String, Profile>,
+    /// Maximum number of profiles per source that are not named, but made up by clients
+    max_ad_hoc_profiles: usize,
+}
+
 #[derive(clap::Args, Debug)]
-#[group(id = "sender", required = true, multiple = false)]
+#[group(id = "sender")]
 pub struct Args {
-    /// TCP socket address to listen on
+    #[command(flatten)]
+    listen: Listen,
+    /// Maximum number of seconds a request to `/next.jpeg` waits for a new image
+    #[arg(long, default_value_t = 30)]
+    long_poll_timeout: u64,
+    /// Maximum number of images per second sent to a single client of `/image.jpeg`
+    #[arg(long, value_parser = crate::parse_fps)]
+    max_client_fps: Option<Duration>,
+    /// Number of idle seconds after which `/image.jpeg` re-sends the latest image, 0 to disable
+    #[arg(long, default_value_t = 30)]
+    keep_alive: u64,
+    /// Maximum number of seconds since the last image of each required source for `/readyz`
+    #[arg(long, default_value_t = 10)]
+    ready_max_age: u64,
+    /// Named profile to scale and re-encode images, e.g. `mobile:width=320,quality=60`; the index
+    /// page uses the profile `thumbnail`, which is `thumbnail:width=320` unless given
+    #[arg(long = "profile", value_name = "NAME:SPEC")]
+    profiles: Vec<NamedProfile>,
+    /// Maximum number of different `width`, `height` and `quality` combinations per stream that
+    /// clients may request at the same time, 0 to only allow `--profile`s
+    #[arg(long, default_value_t = 8)]
+    max_ad_hoc_profiles: usize,
+    #[command(flatten)]
+    auth: auth::Args,
+    #[command(flatten)]
+    tls: tls::Args,
+    /// Tell systemd that the service is ready only after every required source sent an image,
+    /// instead of as soon as the listeners are up
     #[arg(long)]
+    notify_after_first_frame: bool,
+}
+
+#[derive(clap::Args, Debug)]
+#[group(id = "listen")]
+struct Listen {
+    /// TCP socket address to listen on, can be given multiple times
+    #[arg(long)]
+    tcp: Vec<String>,
+    /// Unix domain socket path to bind to, can be given multiple times; a stale socket of a
+    /// previous run is replaced, and the socket is removed on shutdown
+    #[arg(long)]
+    uds: Vec<PathBuf>,
+    #[command(flatten)]
+    permissions: uds::Permissions,
+}
+
+/// A listener as given in the configuration file.
+///
+/// ```toml
+/// [[listener]]
+/// tcp = "[::]:8443"
+/// tls = { cert = "/etc/ssl/camera.pem", key = "/etc/ssl/camera.key" }
+/// auth = { htpasswd = "/etc/mjpeg-restream/htpasswd" }
+///
+/// [[listener]]
+/// uds = "/run/mjpeg-restream/recorder.sock"
+/// auth = {}
+/// permissions = { mode = "660", group = "recorder" }
+///
+/// [[listener]]
+/// fd_name = "https"
+/// tls = { cert = "/etc/ssl/camera.pem", key = "/etc/ssl/camera.key" }
+/// ```
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// TCP socket address to listen on
+    #[serde(default)]
     tcp: Option<String>,
     /// Unix domain socket path to bind to
-    #[arg(long)]
+    #[serde(default)]
     uds: Option<PathBuf>,
+    /// `FileDescriptorName=` of the sockets passed by systemd to use instead of `tcp` or `uds`;
+    /// passed sockets without a listener use the settings of the command line
+    #[serde(default)]
+    fd_name: Option<String>,
+    /// Certificate to serve HTTPS, only for TCP listeners
+    #[serde(default)]
+    tls: Option<tls::Config>,
+    /// Authentication settings, the ones given on the command line if omitted
+    #[serde(default)]
+    auth: Option<auth::Args>,
+    /// Mode, owner and group of the socket file, only for Unix sockets; the ones given on the
+    /// command line if omitted
+    #[serde(default)]
+    permissions: Option<uds::Permissions>,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Could not start blocking thread")]
     JoinBlocking(#[source] tokio::task::JoinError),
-    #[error("Could not start TCP listener")]
-    Tcp(#[source] std::io::Error),
-    #[error("Could not start UDS listener")]
-    Uds(#[source] std::io::Error),
+    #[error("Could not set up authentication")]
+    Auth(#[source] auth::Error),
+    #[error("Could not set up TLS")]
+    Tls(#[source] tls::Error),
+    #[error("No listener was configured, use --tcp, --uds, --config or socket activation")]
+    NoListener,
+    #[error("Invalid listener: {0}")]
+    Listener(String),
+    #[error("Could not start TCP listener {0:?}")]
+    Tcp(String, #[source] std::io::Error),
+    #[error("Could not prepare Unix socket")]
+    Socket(#[source] uds::Error),
+    #[error("Could not start UDS listener {0:?}")]
+    Uds(PathBuf, #[source] std::io::Error),
+    #[error("Could not start listener on the sockets {0:?} passed by systemd")]
+    Inherited(String, #[source] std::io::Error),
     #[error("Could not run server")]
     Run(#[source] std::io::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn etags() {
+        let seq = NonZeroU64::new(42).unwrap();
+        assert_eq!(parse_etag(&etag(seq)), Some(42));
+        assert_eq!(
+            parse_etag(&EntityTag::new_weak(etag(seq).tag().to_owned())),
+            Some(42)
+        );
+
+        // of a previous run, or made up
+        let other_run = EntityTag::new_strong(format!("{:x}-42", *RUN - 1));
+        assert_eq!(parse_etag(&other_run), None);
+        assert_eq!(parse_etag(&EntityTag::new_strong("42".to_owned())), None);
+        assert_eq!(
+            parse_etag(&EntityTag::new_strong("xyz-42".to_owned())),
+            None
+        );
+        let malformed = EntityTag::new_strong(format!("{:x}-x", *RUN));
+        assert_eq!(parse_etag(&malformed), None);
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let tag = etag(NonZeroU64::new(7).unwrap());
+        let request = |value: Option<&str>| {
+            let req = TestRequest::default();
+            match value {
+                Some(value) => req.insert_header((http::header::IF_NONE_MATCH, value)),
+                None => req,
+            }
+            .to_http_request()
+        };
+        assert_eq!(if_none_match(&request(None), &tag), None);
+        assert_eq!(if_none_match(&request(Some("*")), &tag), Some(true));
+        assert_eq!(
+            if_none_match(&request(Some(&format!("\"x\", W/{tag}"))), &tag),
+            Some(true),
+        );
+        let other = etag(NonZeroU64::new(6).unwrap());
+        assert_eq!(
+            if_none_match(&request(Some(&other.to_string())), &tag),
+            Some(false),
+        );
+        assert_eq!(if_none_match(&request(Some("garbage")), &tag), Some(false));
+    }
+}
@@ -0,0 +1,131 @@
+//! A placeholder image that is shown to clients while a source is offline.
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use http::HeaderMap;
+use image::codecs::jpeg::JpegEncoder;
+use image::{Rgb, RgbImage, Rgba};
+use tokio::task::spawn_blocking;
+use tokio::time::sleep;
+
+use crate::frame::Frame;
+use crate::jpeg::Info;
+use crate::listener::ErrorCategory;
+use crate::overlay::draw_text;
+use crate::source::{Source, State};
+
+/// Publishes a placeholder image when `source` has been offline for too long. Never returns.
+///
+/// The placeholder is replaced as soon as a live image arrives.
+pub async fn slate(source: &'static Source) {
+    let mut shown = None;
+    loop {
+        sleep(CHECK_INTERVAL).await;
+        let Some(outage) = Outage::of(source) else {
+            shown = None;
+            continue;
+        };
+        if shown == Some(outage) {
+            continue;
+        }
+
+        let size = match source.holder.get().await {
+            Some((_, frame)) => {
+                Info::parse(&frame.body).map(|info| (u32::from(info.width), u32::from(info.height)))
+            },
+            None => None,
+        };
+        let data = spawn_blocking(move || render(&source.name, outage, size)).await;
+        let data = match data {
+            Ok(Ok(data)) => data,
+            Ok(Err(err)) => {
+                eprintln!("{err:?}");
+                continue;
+            },
+            Err(err) => {
+                eprintln!("{err:?}");
+                continue;
+            },
+        };
+        // a live image may have arrived in the meantime
+        if Outage::of(source) == Some(outage) {
+            let frame = Frame {
+                placeholder: true,
+                ..Frame::new(&data, SystemTime::now(), HeaderMap::new())
+            };
+            source.publish(frame).await;
+            shown = Some(outage);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Outage {
+    since: SystemTime,
+    error: Option<ErrorCategory>,
+}
+
+impl Outage {
+    /// The current outage of `source`, if it lasted longer than `offline_after`.
+    fn of(source: &Source) -> Option<Self> {
+        let status = source.status();
+        let outage = match status.state {
+            State::Connecting | State::Backoff => Outage {
+                since: status.since,
+                error: status.last_error,
+            },
+            State::Streaming => Outage {
+                since: status.last_part.unwrap_or(status.since),
+                error: Some(ErrorCategory::Stalled),
+            },
+        };
+        let elapsed = SystemTime::now()
+            .duration_since(outage.since)
+            .unwrap_or_default();
+        (elapsed >= source.offline_after).then_some(outage)
+    }
+}
+
+fn render(name: &str, outage: Outage, size: Option<(u32, u32)>) -> anyhow::Result<Vec<u8>> {
+    let (width, height) = size.unwrap_or(DEFAULT_SIZE);
+    let since = DateTime::<Local>::from(outage.since).format("%H:%M");
+    let reason = outage
+        .error
+        .map_or("not connected yet", ErrorCategory::as_str);
+    let text = format!("{name}\noffline since {since}\n{reason}");
+
+    // the glyphs of the fonts are about half as wide as they are high
+    let columns = text.lines().map(|line| line.chars().count()).max();
+    let columns = columns.unwrap_or(0) as u32 + 2;
+    let font_size = (height / 12).min(2 * width / columns).max(10);
+
+    let mut image = RgbImage::from_pixel(width, height, BACKGROUND);
+    draw_text(
+        &mut image,
+        &text,
+        font_size,
+        (Rgba([0xff, 0xff, 0xff, 0xff]), Rgba([0, 0, 0, 0])),
+        |(box_width, box_height), _| {
+            (
+                width.saturating_sub(box_width) / 2,
+                height.saturating_sub(box_height) / 2,
+            )
+        },
+    );
+
+    let mut data = Vec::new();
+    JpegEncoder::new_with_quality(&mut data, 80)
+        .encode_image(&image)
+        .context("Could not encode placeholder image")?;
+    Ok(data)
+}
+
+/// Size of the placeholder if no image was received yet.
+const DEFAULT_SIZE: (u32, u32) = (640, 480);
+
+const BACKGROUND: Rgb<u8> = Rgb([0x20, 0x20, 0x20]);
+
+/// How often the state of the source is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
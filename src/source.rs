@@ -0,0 +1,400 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use once_cell::sync::OnceCell;
+use reqwest::Url;
+use tokio::sync::broadcast;
+
+use crate::events::Event;
+use crate::filter::{Chain, FilterConfig};
+use crate::frame::Frame;
+use crate::jpeg::Info;
+use crate::listener::ErrorCategory;
+use crate::metrics::metrics;
+use crate::update_stream::UpdateStream;
+use crate::variants::Variants;
+
+/// An upstream MJPEG stream, and the latest image received from it.
+pub struct Source {
+    /// Unique name of the source, used in URLs like `/streams/{name}/image.jpeg`
+    pub name: String,
+    pub url: Url,
+    /// Minimum time between two restreamed images
+    pub max_input_interval: Option<Duration>,
+    /// Drop images that are identical to the previous image
+    pub skip_duplicates: bool,
+    /// Whether `/readyz` fails if the source does not send images
+    pub required: bool,
+    /// Time without images until clients are shown an "offline" placeholder
+    pub offline_after: Duration,
+    /// Anyone may view the source, even if authentication is enabled
+    pub public: bool,
+    /// Users and tokens that may view the source, `None` for everyone who authenticated
+    pub allow: Option<Vec<String>>,
+    pub chain: Chain,
+    pub holder: UpdateStream<Frame>,
+    pub variants: Variants,
+    status: Mutex<Status>,
+    rate: Mutex<Rate>,
+    events: broadcast::Sender<Event>,
+    clients: AtomicUsize,
+}
+
+/// The state of the connection to the upstream.
+#[derive(Debug, Clone, Copy)]
+pub struct Status {
+    pub state: State,
+    /// When the source started or stopped streaming, or when the program was started
+    pub since: SystemTime,
+    /// When the last image was received, even if it was dropped
+    pub last_part: Option<SystemTime>,
+    /// Why the connection was lost the last time
+    pub last_error: Option<ErrorCategory>,
+    /// Size of the last image
+    pub resolution: Option<Info>,
+    /// Number of connection attempts after a lost connection
+    pub reconnects: u64,
+}
+
+/// Sizes of the images received during the last [`RATE_WINDOW`].
+#[derive(Debug, Default)]
+struct Rate {
+    parts: VecDeque<(Instant, usize)>,
+}
+
+impl Rate {
+    fn record(&mut self, now: Instant, size: usize) {
+        self.parts.push_back((now, size));
+        self.trim(now);
+    }
+
+    fn get(&mut self, now: Instant) -> (f64, f64) {
+        self.trim(now);
+        let window = RATE_WINDOW.as_secs_f64();
+        let bytes = self.parts.iter().map(|&(_, size)| size).sum::<usize>();
+        (self.parts.len() as f64 / window, bytes as f64 / window)
+    }
+
+    fn trim(&mut self, now: Instant) {
+        while let Some(&(time, _)) = self.parts.front() {
+            if now.duration_since(time) <= RATE_WINDOW {
+                break;
+            }
+            let _ = self.parts.pop_front();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum State {
+    /// Waiting for the upstream to answer
+    Connecting,
+    /// Receiving images
+    Streaming,
+    /// Waiting before the next connection attempt
+    Backoff,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Connecting => "connecting",
+            State::Streaming => "streaming",
+            State::Backoff => "backoff",
+        }
+    }
+}
+
+/// A source as given in the configuration file, or on the command line.
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    pub name: String,
+    #[serde(deserialize_with = "crate::deserialize_from_str")]
+    pub url: Url,
+    #[serde(default)]
+    pub max_input_fps: Option<f64>,
+    #[serde(default)]
+    pub skip_duplicates: bool,
+    /// Whether `/readyz` fails if the source does not send images
+    #[serde(default = "default_required")]
+    pub required: bool,
+    /// Number of seconds without images until clients are shown an "offline" placeholder
+    #[serde(default = "default_offline_after")]
+    pub offline_after: u64,
+    /// Anyone may view the source, even if authentication is enabled
+    #[serde(default)]
+    pub public: bool,
+    /// Users and tokens that may view the source, by default everyone who authenticated
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    /// JPEG quality of images that had to be modified
+    #[serde(default = "default_quality")]
+    pub quality: u8,
+    /// Filters in the order they are applied
+    #[serde(default)]
+    pub filters: Vec<FilterConfig>,
+}
+
+impl Source {
+    /// Prepares the source. Blocks, e.g. to read the image of a watermark.
+    pub fn new(config: SourceConfig) -> Result<Self, Error> {
+        let SourceConfig {
+            name,
+            url,
+            max_input_fps,
+            skip_duplicates,
+            required,
+            offline_after,
+            public,
+            allow,
+            quality,
+            filters,
+        } = config;
+
+        let max_input_interval = max_input_fps
+            .map(crate::fps_to_interval)
+            .transpose()
+            .map_err(|msg| Error::Invalid(name.clone(), msg))?;
+        if !(1..=100).contains(&quality) {
+            let msg = format!("Quality must be in 1 ..= 100, not {quality}");
+            return Err(Error::Invalid(name, msg));
+        }
+        if offline_after == 0 {
+            let msg = "The outage threshold must be at least one second".to_owned();
+            return Err(Error::Invalid(name, msg));
+        }
+        let filters = filters
+            .into_iter()
+            .map(FilterConfig::build)
+            .collect::<Result<_, _>>()
+            .map_err(|err| Error::Filter(name.clone(), err))?;
+        let chain = Chain::new(&name, filters, quality);
+
+        Ok(Self {
+            name,
+            url,
+            max_input_interval,
+            skip_duplicates,
+            required,
+            offline_after: Duration::from_secs(offline_after),
+            public,
+            allow,
+            chain,
+            holder: UpdateStream::default(),
+            variants: Variants::default(),
+            status: Mutex::new(Status {
+                state: State::Connecting,
+                since: SystemTime::now(),
+                last_part: None,
+                last_error: None,
+                resolution: None,
+                reconnects: 0,
+            }),
+            rate: Mutex::default(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            clients: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn status(&self) -> Status {
+        *self.status.lock().unwrap()
+    }
+
+    pub fn set_state(&self, state: State) {
+        let mut status = self.status.lock().unwrap();
+        if status.state == state {
+            return;
+        }
+        if (status.state, state) == (State::Backoff, State::Connecting) {
+            status.reconnects += 1;
+        }
+        if (status.state == State::Streaming) != (state == State::Streaming) {
+            status.since = SystemTime::now();
+        }
+        status.state = state;
+        drop(status);
+        self.send(Event::State { state });
+    }
+
+    /// Records that an image was received, even if it is dropped later on.
+    pub fn set_online(&self, now: SystemTime, size: usize) {
+        self.status.lock().unwrap().last_part = Some(now);
+        self.rate.lock().unwrap().record(Instant::now(), size);
+        self.set_state(State::Streaming);
+    }
+
+    /// Images and bytes per second received from the upstream recently.
+    pub fn rate(&self) -> (f64, f64) {
+        self.rate.lock().unwrap().get(Instant::now())
+    }
+
+    /// Records that the connection was lost.
+    pub fn set_error(&self, category: ErrorCategory) {
+        self.status.lock().unwrap().last_error = Some(category);
+        self.send(Event::Error { category });
+    }
+
+    /// Makes a received image, or a placeholder, available to the clients.
+    pub async fn publish(&self, frame: Frame) {
+        let placeholder = frame.placeholder;
+        let info = Info::parse(&frame.body);
+        let size = frame.body.len();
+        let received = frame.received;
+        let sequence = self.holder.update(frame).await;
+        self.variants.forget_unused();
+        metrics()
+            .frames_published
+            .with_label_values(&[&self.name])
+            .inc();
+
+        // the size of a placeholder is made up if no image was received yet
+        if !placeholder {
+            let previous = std::mem::replace(&mut self.status.lock().unwrap().resolution, info);
+            if let Some(info) = info.filter(|&info| Some(info) != previous) {
+                self.send(Event::Resolution {
+                    width: info.width,
+                    height: info.height,
+                });
+            }
+        }
+        self.send(Event::Frame {
+            sequence: sequence.get(),
+            size,
+            received: crate::millis_since_epoch(received),
+            placeholder,
+        });
+    }
+
+    /// Receives all future events of this source.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    fn send(&self, event: Event) {
+        // there may be no subscribers
+        let _ = self.events.send(event);
+    }
+
+    /// Number of clients that currently stream images.
+    pub fn clients(&self) -> usize {
+        self.clients.load(Ordering::Relaxed)
+    }
+
+    /// Counts a client until the guard is dropped.
+    pub fn client(&'static self) -> ClientGuard {
+        metrics()
+            .clients_total
+            .with_label_values(&[&self.name])
+            .inc();
+        let count = self.clients.fetch_add(1, Ordering::Relaxed) + 1;
+        self.send(Event::Clients { count });
+        ClientGuard(self)
+    }
+}
+
+/// A client that streams images, see [`Source::client()`].
+pub struct ClientGuard(&'static Source);
+
+impl fmt::Debug for ClientGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ClientGuard").field(&self.0.name).finish()
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        let count = self.0.clients.fetch_sub(1, Ordering::Relaxed) - 1;
+        self.0.send(Event::Clients { count });
+    }
+}
+
+/// Makes the sources available through [`sources()`]. Can only be called once.
+pub fn init(sources: Vec<Source>) -> Result<(), Error> {
+    for (idx, source) in sources.iter().enumerate() {
+        if sources[..idx].iter().any(|other| other.name == source.name) {
+            return Err(Error::Duplicate(source.name.clone()));
+        }
+    }
+    if sources.is_empty() {
+        return Err(Error::Empty);
+    }
+    SOURCES.set(sources).map_err(|_| Error::Initialized)
+}
+
+/// All configured sources, the first one is the default source.
+pub fn sources() -> &'static [Source] {
+    SOURCES.get().map_or(&[], Vec::as_slice)
+}
+
+/// The source with the given name, or the default source if `name` is `None`.
+pub fn find(name: Option<&str>) -> Option<&'static Source> {
+    match name {
+        Some(name) => sources().iter().find(|source| source.name == name),
+        None => sources().first(),
+    }
+}
+
+static SOURCES: OnceCell<Vec<Source>> = OnceCell::new();
+
+/// Time span over which the frame rate and bitrate are measured.
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Number of events that are buffered for slow subscribers.
+const EVENT_CAPACITY: usize = 64;
+
+fn default_required() -> bool {
+    true
+}
+
+pub fn default_offline_after() -> u64 {
+    10
+}
+
+pub fn default_quality() -> u8 {
+    90
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Source {0:?} is invalid: {1}")]
+    Invalid(String, String),
+    #[error("Could not create filter of source {0:?}")]
+    Filter(String, #[source] crate::filter::Error),
+    #[error("The name of source {0:?} is not unique")]
+    Duplicate(String),
+    #[error("No source was configured, use --url or --config")]
+    Empty,
+    #[error("The sources were already initialized")]
+    Initialized,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate() {
+        assert_eq!(RATE_WINDOW, Duration::from_secs(10));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut rate = Rate::default();
+        assert_eq!(rate.get(start), (0.0, 0.0));
+
+        for ms in [0, 2500, 5000, 7500] {
+            rate.record(at(ms), 1000);
+        }
+        assert_eq!(rate.get(at(10_000)), (0.4, 400.0));
+        // the first image leaves the window
+        assert_eq!(rate.get(at(10_001)), (0.3, 300.0));
+        rate.record(at(10_002), 3000);
+        assert_eq!(rate.get(at(10_002)), (0.4, 600.0));
+
+        assert_eq!(rate.get(at(30_000)), (0.0, 0.0));
+        assert!(rate.parts.is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+//! Socket activation and readiness notification when running as a systemd service.
+//!
+//! Outside of systemd, no sockets are passed and the notifications are ignored.
+
+use std::env;
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::time::Duration;
+
+use listenfd::ListenFd;
+use sd_notify::NotifyState;
+use tokio::time::sleep;
+
+use crate::source;
+
+/// A listening socket that was passed with `LISTEN_FDS`.
+#[derive(Debug)]
+pub struct Inherited {
+    /// The `FileDescriptorName=` of the socket unit
+    pub name: String,
+    pub socket: Socket,
+}
+
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// Takes the sockets passed by systemd. Must be called before any thread is started.
+pub fn listen_fds() -> Result<Vec<Inherited>, Error> {
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    env::remove_var("LISTEN_FDNAMES");
+    let mut names = names.split(':');
+
+    let mut fds = ListenFd::from_env();
+    (0..fds.len())
+        .map(|idx| {
+            let socket = match fds.take_tcp_listener(idx) {
+                Ok(socket) => socket.map(Socket::Tcp),
+                Err(_) => fds
+                    .take_unix_listener(idx)
+                    .map_err(|err| Error::Socket(idx, err))?
+                    .map(Socket::Unix),
+            };
+            let name = names.next().unwrap_or_default();
+            Ok(Inherited {
+                name: if name.is_empty() { "unknown" } else { name }.to_owned(),
+                socket: socket.ok_or(Error::Taken(idx))?,
+            })
+        })
+        .collect()
+}
+
+/// Sends `READY=1`, if `after_first_frame` only after every required source sent an image.
+pub async fn notify_ready(after_first_frame: bool) {
+    while after_first_frame
+        && source::sources()
+            .iter()
+            .any(|source| source.required && source.status().last_part.is_none())
+    {
+        sleep(READY_CHECK_INTERVAL).await;
+    }
+    notify(&[NotifyState::Ready]);
+}
+
+pub fn notify_stopping() {
+    notify(&[NotifyState::Stopping]);
+}
+
+/// Pings the watchdog at half its interval, if `WatchdogSec=` is set. Never returns.
+///
+/// The pings are sent from the main runtime, so they stop if it gets stuck.
+pub async fn watchdog() {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return std::future::pending().await;
+    }
+    let interval = Duration::from_micros(usec) / 2;
+    loop {
+        notify(&[NotifyState::Watchdog]);
+        sleep(interval).await;
+    }
+}
+
+fn notify(state: &[NotifyState<'_>]) {
+    if let Err(err) = sd_notify::notify(false, state) {
+        eprintln!("{:?}", anyhow::Error::new(Error::Notify(err)));
+    }
+}
+
+/// How often `notify_ready()` checks if the required sources sent an image.
+const READY_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Passed file descriptor {0} is neither a TCP nor a Unix stream socket")]
+    Socket(usize, #[source] std::io::Error),
+    #[error("Passed file descriptor {0} was already taken")]
+    Taken(usize),
+    #[error("Could not notify systemd")]
+    Notify(#[source] std::io::Error),
+}
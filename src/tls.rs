@@ -0,0 +1,195 @@
+//! HTTPS with a certificate that is reloaded without dropping the connected clients.
+
+use std::fmt;
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use once_cell::sync::Lazy;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{PrivateKey, ServerConfig};
+use tokio::select;
+use tokio::sync::watch;
+use tokio::task::spawn_blocking;
+use tokio::time::sleep;
+
+/// Serves the certificate that was loaded last.
+pub struct CertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertResolver")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CertResolver {
+    /// Reads the certificate chain and the private key. Blocks.
+    pub fn load(config: Config) -> Result<Self, Error> {
+        let Config {
+            cert: cert_path,
+            key: key_path,
+        } = config;
+        let key = load(&cert_path, &key_path)?;
+        Ok(Self {
+            cert_path,
+            key_path,
+            key: RwLock::new(Arc::new(key)),
+        })
+    }
+
+    pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+        let resolver = Arc::clone(self);
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver)
+    }
+
+    /// Reloads the certificate on [`reload()`], or when one of the files was modified.
+    ///
+    /// If the new files cannot be used, the old certificate stays in use.
+    pub fn watch(self: Arc<Self>) -> impl Future<Output = ()> {
+        let mut reload = RELOAD.subscribe();
+        async move {
+            let mut modified = self.modified();
+            loop {
+                select! {
+                    _ = reload.changed() => {},
+                    () = sleep(RELOAD_CHECK_INTERVAL) => {
+                        if self.modified() == modified {
+                            continue;
+                        }
+                    },
+                }
+                modified = self.modified();
+
+                let this = Arc::clone(&self);
+                let result = spawn_blocking(move || load(&this.cert_path, &this.key_path)).await;
+                match result {
+                    Ok(Ok(key)) => {
+                        *self.key.write().unwrap() = Arc::new(key);
+                        eprintln!("Reloaded certificate {:?}", self.cert_path);
+                    },
+                    Ok(Err(err)) => eprintln!("{:?}", anyhow::Error::new(err)),
+                    Err(err) => eprintln!("{:?}", anyhow::Error::new(err)),
+                }
+            }
+        }
+    }
+
+    fn modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        (modified(&self.cert_path), modified(&self.key_path))
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.key.read().unwrap()))
+    }
+}
+
+/// Makes all [`CertResolver::watch()`]ers reload their certificate, called on SIGHUP.
+pub fn reload() {
+    RELOAD.send_modify(|()| {});
+}
+
+/// Notifies the [`CertResolver::watch()`]ers.
+static RELOAD: Lazy<watch::Sender<()>> = Lazy::new(|| watch::channel(()).0);
+
+fn load(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, Error> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|err| Error::Read(path.to_owned(), err))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(cert_path)?)
+        .map_err(|err| Error::Read(cert_path.to_owned(), err))?;
+    if certs.is_empty() {
+        return Err(Error::NoCertificate(cert_path.to_owned()));
+    }
+
+    let mut reader = open(key_path)?;
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|err| Error::Read(key_path.to_owned(), err))?
+        {
+            Some(
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => break PrivateKey(key),
+            Some(_) => {},
+            None => return Err(Error::NoKey(key_path.to_owned())),
+        }
+    };
+    let key = rustls::sign::any_supported_type(&key)
+        .map_err(|err| Error::Key(key_path.to_owned(), err))?;
+
+    Ok(CertifiedKey::new(
+        certs.into_iter().map(rustls::Certificate).collect(),
+        key,
+    ))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// How often the modification times of the certificate and key are checked.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The certificate of a listener, as given in the configuration file.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// PEM file with the certificate chain
+    pub cert: PathBuf,
+    /// PEM file with the private key
+    pub key: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+#[group(id = "tls")]
+pub struct Args {
+    /// PEM file with the certificate chain, to serve HTTPS instead of HTTP on the `--tcp`
+    /// listeners; reloaded on SIGHUP or when modified
+    #[arg(long, value_name = "PATH", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM file with the private key of `--tls-cert`
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+}
+
+impl Args {
+    /// The certificate, if TLS is enabled.
+    pub fn config(self) -> Option<Config> {
+        let (cert, key) = self.tls_cert.zip(self.tls_key)?;
+        Some(Config { cert, key })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not read {0:?}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("{0:?} does not contain a PEM encoded certificate")]
+    NoCertificate(PathBuf),
+    #[error("{0:?} does not contain a PEM encoded private key")]
+    NoKey(PathBuf),
+    #[error("The private key in {0:?} is not supported")]
+    Key(PathBuf, #[source] rustls::sign::SignError),
+}
@@ -0,0 +1,269 @@
+use std::str::FromStr;
+
+use image::imageops;
+
+use crate::filter::{FilterConfig, FilterFrame, FrameFilter, Verdict};
+use crate::jpeg::Info;
+use crate::lossless::{self, Operation};
+
+/// Geometric transformations, applied in the order crop, rotate, flip.
+///
+/// If possible, the JPEG image is transformed without decoding it, see [`lossless`].
+#[derive(clap::Args, Debug, Clone, Copy)]
+#[group(id = "transform")]
+pub struct Transform {
+    /// Only keep this region of the image, given as `WIDTHxHEIGHT+X+Y`; a region that exceeds the
+    /// image is moved and shrunk to fit. Unless X and Y are multiples of 16, the image may have to
+    /// be decoded and encoded again
+    #[arg(long)]
+    crop: Option<Rect>,
+    /// Rotate the image clockwise
+    #[arg(long)]
+    rotate: Option<Rotation>,
+    /// Mirror the image
+    #[arg(long)]
+    flip: Option<Flip>,
+}
+
+impl Transform {
+    pub fn filters(self) -> impl Iterator<Item = FilterConfig> {
+        [
+            self.crop.map(|rect| FilterConfig::Crop { rect }),
+            self.rotate.map(|degrees| FilterConfig::Rotate { degrees }),
+            self.flip.map(|direction| FilterConfig::Flip { direction }),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+#[derive(clap::ValueEnum, serde::Deserialize, Debug, Clone, Copy)]
+#[serde(try_from = "u16")]
+pub enum Rotation {
+    #[value(name = "90")]
+    Rotate90,
+    #[value(name = "180")]
+    Rotate180,
+    #[value(name = "270")]
+    Rotate270,
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u16) -> Result<Self, Self::Error> {
+        match degrees {
+            90 => Ok(Rotation::Rotate90),
+            180 => Ok(Rotation::Rotate180),
+            270 => Ok(Rotation::Rotate270),
+            _ => Err(format!("Expected 90, 180 or 270, got {degrees}")),
+        }
+    }
+}
+
+impl FrameFilter for Rotation {
+    fn name(&self) -> &'static str {
+        "rotate"
+    }
+
+    fn apply(&self, frame: &mut FilterFrame) -> anyhow::Result<Verdict> {
+        let operation = match self {
+            Rotation::Rotate90 => Operation::Rotate90,
+            Rotation::Rotate180 => Operation::Rotate180,
+            Rotation::Rotate270 => Operation::Rotate270,
+        };
+        if apply_lossless(frame, operation) {
+            return Ok(Verdict::Keep);
+        }
+        let image = frame.image()?;
+        let image = match self {
+            Rotation::Rotate90 => imageops::rotate90(image),
+            Rotation::Rotate180 => imageops::rotate180(image),
+            Rotation::Rotate270 => imageops::rotate270(image),
+        };
+        frame.set_image(image);
+        Ok(Verdict::Keep)
+    }
+}
+
+#[derive(clap::ValueEnum, serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Flip {
+    Horizontal,
+    Vertical,
+    Both,
+}
+
+impl FrameFilter for Flip {
+    fn name(&self) -> &'static str {
+        "flip"
+    }
+
+    fn apply(&self, frame: &mut FilterFrame) -> anyhow::Result<Verdict> {
+        let operation = match self {
+            Flip::Horizontal => Operation::FlipHorizontal,
+            Flip::Vertical => Operation::FlipVertical,
+            Flip::Both => Operation::Rotate180,
+        };
+        if apply_lossless(frame, operation) {
+            return Ok(Verdict::Keep);
+        }
+        let image = frame.image()?;
+        match self {
+            Flip::Horizontal => imageops::flip_horizontal_in_place(image),
+            Flip::Vertical => imageops::flip_vertical_in_place(image),
+            Flip::Both => imageops::rotate180_in_place(image),
+        }
+        Ok(Verdict::Keep)
+    }
+}
+
+/// A rectangle, given as `WIDTHxHEIGHT+X+Y`.
+///
+/// As a filter, only the region of the image inside the rectangle is kept.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FrameFilter for Rect {
+    fn name(&self) -> &'static str {
+        "crop"
+    }
+
+    fn apply(&self, frame: &mut FilterFrame) -> anyhow::Result<Verdict> {
+        if let Some(info) = frame.jpeg().and_then(|jpeg| Info::parse(jpeg)) {
+            let rect = self.clamp(u32::from(info.width), u32::from(info.height));
+            // all values fit, because they are clamped to the size of the image
+            let operation = Operation::Crop {
+                x: rect.x as u16,
+                y: rect.y as u16,
+                width: rect.width as u16,
+                height: rect.height as u16,
+            };
+            if apply_lossless(frame, operation) {
+                return Ok(Verdict::Keep);
+            }
+            // unlike jpegtran, the region is not extended to the next MCU boundary, but decoded
+        }
+        let image = frame.image()?;
+        let rect = self.clamp(image.width(), image.height());
+        let image = imageops::crop_imm(image, rect.x, rect.y, rect.width, rect.height).to_image();
+        frame.set_image(image);
+        Ok(Verdict::Keep)
+    }
+}
+
+impl Rect {
+    /// Moves and shrinks the rectangle to fit into an image of the given size, so that a
+    /// rectangle that exceeds the image still yields an image of the expected size if possible.
+    fn clamp(self, width: u32, height: u32) -> Self {
+        let (clamped_width, clamped_height) = (self.width.min(width), self.height.min(height));
+        Self {
+            x: self.x.min(width - clamped_width),
+            y: self.y.min(height - clamped_height),
+            width: clamped_width,
+            height: clamped_height,
+        }
+    }
+}
+
+/// Transforms the JPEG image without decoding it, if it was not decoded by a previous filter.
+/// Returns `false` if the image has to be decoded.
+fn apply_lossless(frame: &mut FilterFrame, operation: Operation) -> bool {
+    match frame
+        .jpeg()
+        .and_then(|jpeg| lossless::transform(jpeg, operation))
+    {
+        Some(jpeg) => {
+            frame.set_jpeg(jpeg.into());
+            true
+        },
+        None => false,
+    }
+}
+
+impl FromStr for Rect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Expected WIDTHxHEIGHT+X+Y, got {s:?}");
+        let (size, offset) = s.split_once('+').ok_or_else(err)?;
+        let (width, height) = size.split_once('x').ok_or_else(err)?;
+        let (x, y) = offset.split_once('+').ok_or_else(err)?;
+        let [x, y, width, height] = [x, y, width, height].map(|v| v.parse::<u32>());
+        let rect = Rect {
+            x: x.map_err(|_| err())?,
+            y: y.map_err(|_| err())?,
+            width: width.map_err(|_| err())?,
+            height: height.map_err(|_| err())?,
+        };
+        if rect.width == 0 || rect.height == 0 {
+            return Err("Rectangle must not be empty".to_owned());
+        }
+        Ok(rect)
+    }
+}
+
+impl TryFrom<String> for Rect {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(width: u32, height: u32, x: u32, y: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn parse_rect() {
+        assert_eq!("640x480+16+32".parse(), Ok(rect(640, 480, 16, 32)));
+        assert_eq!("1x1+0+0".parse(), Ok(rect(1, 1, 0, 0)));
+        for s in [
+            "",
+            "640x480",
+            "640x480+16",
+            "640+480+16+32",
+            "640x480+16+32+0",
+            "-640x480+16+32",
+            "640x480+-16+32",
+            "640xx480+16+32",
+            "640x480+16+y",
+            "0x480+16+32",
+            "640x0+16+32",
+        ] {
+            assert!(s.parse::<Rect>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn clamp() {
+        // inside of the image
+        assert_eq!(rect(20, 10, 5, 5).clamp(100, 50), rect(20, 10, 5, 5));
+        assert_eq!(rect(20, 10, 80, 40).clamp(100, 50), rect(20, 10, 80, 40));
+        // moved
+        assert_eq!(rect(20, 10, 90, 45).clamp(100, 50), rect(20, 10, 80, 40));
+        assert_eq!(
+            rect(20, 10, u32::MAX, u32::MAX).clamp(100, 50),
+            rect(20, 10, 80, 40),
+        );
+        // shrunk
+        assert_eq!(rect(200, 60, 5, 5).clamp(100, 50), rect(100, 50, 0, 0));
+        assert_eq!(rect(u32::MAX, 10, 0, 0).clamp(100, 50), rect(100, 10, 0, 0),);
+    }
+}
@@ -0,0 +1,324 @@
+//! Unix domain sockets that clean up after themselves.
+
+use std::fs::{self, DirBuilder};
+use std::io::ErrorKind;
+use std::os::unix::fs::{chown, DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nix::errno::Errno;
+use nix::fcntl::{renameat2, RenameFlags};
+use nix::unistd::{Group, User};
+
+/// Binds the socket, after removing a stale socket file of a previous run. Blocks.
+///
+/// The socket is bound in a private directory, and only moved to `path` after its permissions were
+/// set, so that nobody can connect before. The move never replaces a socket that another process
+/// bound in the meantime.
+pub fn bind(path: &Path, permissions: &Permissions) -> Result<(UnixListener, SocketFile), Error> {
+    remove_stale(path)?;
+    let dir = PrivateDir::create(path)?;
+    let tmp_path = dir.0.join("s");
+    let listener =
+        UnixListener::bind(&tmp_path).map_err(|err| Error::Bind(path.to_owned(), err))?;
+    permissions.apply(&tmp_path)?;
+    let mut attempts = 0;
+    loop {
+        match renameat2(None, &tmp_path, None, path, RenameFlags::RENAME_NOREPLACE) {
+            Ok(()) => break,
+            // e.g. a stale socket of another instance that was started at the same time
+            Err(Errno::EEXIST) if attempts < RENAME_ATTEMPTS => {
+                attempts += 1;
+                remove_stale(path)?;
+            },
+            Err(err) => return Err(Error::Rename(path.to_owned(), err.into())),
+        }
+    }
+    let file = SocketFile::new(path)?;
+    Ok((listener, file))
+}
+
+/// How often a socket file that appeared while binding is removed before giving up.
+const RENAME_ATTEMPTS: u32 = 3;
+
+/// A directory next to the socket that only this user can access, removed when dropped.
+struct PrivateDir(PathBuf);
+
+impl PrivateDir {
+    fn create(path: &Path) -> Result<Self, Error> {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        // short, because the path of a socket is limited to about 100 bytes
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let dir = parent.join(format!(".uds-{}-{nanos:x}", process::id()));
+        DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .map_err(|err| Error::PrivateDir(dir.clone(), err))?;
+        Ok(Self(dir))
+    }
+}
+
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        // still contains the socket if it could not be moved
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Removes `path` if it is a socket that nobody listens on.
+fn remove_stale(path: &Path) -> Result<(), Error> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(Error::Stat(path.to_owned(), err)),
+    };
+    if !meta.file_type().is_socket() {
+        return Err(Error::NotSocket(path.to_owned()));
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => Err(Error::InUse(path.to_owned())),
+        Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+            eprintln!("Removing stale socket {path:?}");
+            fs::remove_file(path).map_err(|err| Error::Remove(path.to_owned(), err))
+        },
+        Err(err) => Err(Error::Connect(path.to_owned(), err)),
+    }
+}
+
+/// Removes the socket file when dropped, unless it was replaced in the meantime.
+#[derive(Debug)]
+pub struct SocketFile {
+    path: PathBuf,
+    /// Device and inode number of the socket
+    id: (u64, u64),
+}
+
+impl SocketFile {
+    fn new(path: &Path) -> Result<Self, Error> {
+        let meta = fs::symlink_metadata(path).map_err(|err| Error::Stat(path.to_owned(), err))?;
+        Ok(Self {
+            path: path.to_owned(),
+            id: (meta.dev(), meta.ino()),
+        })
+    }
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let Ok(meta) = fs::symlink_metadata(&self.path) else {
+            return;
+        };
+        if (meta.dev(), meta.ino()) != self.id {
+            return;
+        }
+        if let Err(err) = fs::remove_file(&self.path) {
+            let err = Error::Remove(self.path.clone(), err);
+            eprintln!("{:?}", anyhow::Error::new(err));
+        }
+    }
+}
+
+/// Mode, owner and group of a socket file, given on the command line or for a listener in the
+/// configuration file. If a listener has no settings, the ones of the command line are used.
+#[derive(clap::Args, serde::Deserialize, Debug, Clone, Default)]
+#[group(id = "uds_permissions")]
+#[serde(default, deny_unknown_fields)]
+pub struct Permissions {
+    /// File mode of the `--uds` sockets in octal, e.g. `660`
+    #[arg(long = "uds-mode", value_name = "MODE")]
+    mode: Option<Mode>,
+    /// Owner of the `--uds` sockets, a user name or ID
+    #[arg(long = "uds-owner", value_name = "USER")]
+    owner: Option<String>,
+    /// Group of the `--uds` sockets, a group name or ID
+    #[arg(long = "uds-group", value_name = "GROUP")]
+    group: Option<String>,
+}
+
+impl Permissions {
+    fn apply(&self, path: &Path) -> Result<(), Error> {
+        let uid = match &self.owner {
+            Some(owner) => Some(uid(owner)?),
+            None => None,
+        };
+        let gid = match &self.group {
+            Some(group) => Some(gid(group)?),
+            None => None,
+        };
+        if uid.is_some() || gid.is_some() {
+            chown(path, uid, gid).map_err(|err| Error::Chown(path.to_owned(), err))?;
+        }
+        if let Some(Mode(mode)) = self.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                .map_err(|err| Error::Chmod(path.to_owned(), err))?;
+        }
+        Ok(())
+    }
+}
+
+fn uid(owner: &str) -> Result<u32, Error> {
+    if let Ok(uid) = owner.parse() {
+        return Ok(uid);
+    }
+    match User::from_name(owner) {
+        Ok(Some(user)) => Ok(user.uid.as_raw()),
+        Ok(None) => Err(Error::UnknownUser(owner.to_owned(), None)),
+        Err(err) => Err(Error::UnknownUser(owner.to_owned(), Some(err))),
+    }
+}
+
+fn gid(group: &str) -> Result<u32, Error> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    match Group::from_name(group) {
+        Ok(Some(group)) => Ok(group.gid.as_raw()),
+        Ok(None) => Err(Error::UnknownGroup(group.to_owned(), None)),
+        Err(err) => Err(Error::UnknownGroup(group.to_owned(), Some(err))),
+    }
+}
+
+/// Permission bits, given in octal.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct Mode(u32);
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match u32::from_str_radix(s, 8) {
+            Ok(mode) if mode <= 0o777 => Ok(Self(mode)),
+            _ => Err(format!("Expected an octal mode like 660, got {s:?}")),
+        }
+    }
+}
+
+impl TryFrom<String> for Mode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not inspect {0:?}")]
+    Stat(PathBuf, #[source] std::io::Error),
+    #[error("{0:?} exists and is not a socket")]
+    NotSocket(PathBuf),
+    #[error("Socket {0:?} is in use by another process")]
+    InUse(PathBuf),
+    #[error("Could not check if socket {0:?} is in use")]
+    Connect(PathBuf, #[source] std::io::Error),
+    #[error("Could not remove socket {0:?}")]
+    Remove(PathBuf, #[source] std::io::Error),
+    #[error("Could not bind to {0:?}")]
+    Bind(PathBuf, #[source] std::io::Error),
+    #[error("Could not create the private directory {0:?}")]
+    PrivateDir(PathBuf, #[source] std::io::Error),
+    #[error("Could not move the socket to {0:?}")]
+    Rename(PathBuf, #[source] std::io::Error),
+    #[error("Unknown user {0:?}")]
+    UnknownUser(String, #[source] Option<nix::Error>),
+    #[error("Unknown group {0:?}")]
+    UnknownGroup(String, #[source] Option<nix::Error>),
+    #[error("Could not change the owner of {0:?}")]
+    Chown(PathBuf, #[source] std::io::Error),
+    #[error("Could not change the mode of {0:?}")]
+    Chmod(PathBuf, #[source] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// A path in the temporary directory that no other test uses.
+    fn temp_path() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        std::env::temp_dir().join(format!(
+            "mjpeg-restream-test-{}-{}.sock",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+        ))
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!("660".parse::<Mode>().unwrap().0, 0o660);
+        assert_eq!("0777".parse::<Mode>().unwrap().0, 0o777);
+        assert_eq!("0".parse::<Mode>().unwrap().0, 0);
+        assert!("1000".parse::<Mode>().is_err());
+        assert!("689".parse::<Mode>().is_err());
+        assert!("rw".parse::<Mode>().is_err());
+        assert!("".parse::<Mode>().is_err());
+        assert!(Mode::try_from("-1".to_owned()).is_err());
+    }
+
+    #[test]
+    fn stale_sockets() {
+        let path = temp_path();
+        remove_stale(&path).unwrap();
+
+        fs::write(&path, "").unwrap();
+        assert!(matches!(remove_stale(&path), Err(Error::NotSocket(_))));
+        fs::remove_file(&path).unwrap();
+
+        let listener = UnixListener::bind(&path).unwrap();
+        assert!(matches!(remove_stale(&path), Err(Error::InUse(_))));
+        drop(listener);
+        remove_stale(&path).unwrap();
+        assert!(fs::symlink_metadata(&path).is_err());
+    }
+
+    #[test]
+    fn bind_and_remove() {
+        let path = temp_path();
+        let permissions = Permissions {
+            mode: Some(Mode(0o600)),
+            ..Permissions::default()
+        };
+        let (listener, file) = bind(&path, &permissions).unwrap();
+        let meta = fs::symlink_metadata(&path).unwrap();
+        assert!(meta.file_type().is_socket());
+        assert_eq!(meta.mode() & 0o777, 0o600);
+        assert!(matches!(bind(&path, &permissions), Err(Error::InUse(_)),));
+        let _ = UnixStream::connect(&path).unwrap();
+
+        // a socket of a previous run is replaced
+        drop(listener);
+        drop(file);
+        assert!(fs::symlink_metadata(&path).is_err());
+        let listener = UnixListener::bind(&path).unwrap();
+        drop(listener);
+        let (_listener, file) = bind(&path, &permissions).unwrap();
+        drop(file);
+        assert!(fs::symlink_metadata(&path).is_err());
+    }
+
+    #[test]
+    fn replaced_socket_file() {
+        let path = temp_path();
+        let (listener, file) = bind(&path, &Permissions::default()).unwrap();
+        drop(listener);
+
+        // e.g. another instance removed the stale socket and bound its own
+        fs::remove_file(&path).unwrap();
+        let _other = UnixListener::bind(&path).unwrap();
+        drop(file);
+        assert!(fs::symlink_metadata(&path).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}
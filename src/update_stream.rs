@@ -1,38 +1,85 @@
 use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use async_condvar_fair::Condvar;
 use futures_util::Stream;
 use tokio::sync::RwLock;
 
-#[derive(Default)]
 pub struct UpdateStream<T: Send + Sync + Clone> {
     holder: RwLock<Option<(NonZeroU64, T)>>,
     cv: Condvar,
+    subscribers: AtomicUsize,
+}
+
+impl<T: Send + Sync + Clone> Default for UpdateStream<T> {
+    fn default() -> Self {
+        Self {
+            holder: RwLock::new(None),
+            cv: Condvar::default(),
+            subscribers: AtomicUsize::new(0),
+        }
+    }
 }
 
 impl<T: Send + Sync + Clone> UpdateStream<T> {
-    pub fn stream_updates(&self) -> impl '_ + Stream<Item = T> {
+    pub fn stream_updates(&self) -> impl '_ + Stream<Item = (NonZeroU64, T)> {
         async_stream::stream! {
+            let _subscription = Subscription::new(&self.subscribers);
             let mut idx = 0;
             loop {
-                let guard = self.holder.read().await;
-                if let Some((cur_idx, ref bytes)) = &*guard {
-                    let cur_idx = cur_idx.get();
-                    if cur_idx > idx {
-                        idx = cur_idx;
-                        yield bytes.clone();
-                    }
+                let (cur_idx, value) = self.get_newer(idx).await;
+                idx = cur_idx.get();
+                yield (cur_idx, value);
+            }
+        }
+    }
+
+    /// Number of streams returned by [`Self::stream_updates()`] that are still in use.
+    pub fn subscribers(&self) -> usize {
+        self.subscribers.load(Ordering::Relaxed)
+    }
+
+    /// Waits until a value with a sequence number greater than `idx` was stored.
+    pub async fn get_newer(&self, idx: u64) -> (NonZeroU64, T) {
+        loop {
+            let guard = self.holder.read().await;
+            if let Some((cur_idx, ref value)) = *guard {
+                if cur_idx.get() > idx {
+                    return (cur_idx, value.clone());
                 }
-                let _ = self.cv.wait_no_relock((guard, &self.holder)).await;
             }
+            let _ = self.cv.wait_no_relock((guard, &self.holder)).await;
         }
     }
 
-    pub async fn update(&self, new_data: T) {
+    /// The latest value and its sequence number, if any value was stored yet.
+    pub async fn get(&self) -> Option<(NonZeroU64, T)> {
+        self.holder.read().await.clone()
+    }
+
+    /// Stores a new value, and returns its sequence number.
+    pub async fn update(&self, new_data: T) -> NonZeroU64 {
         let mut guard = self.holder.write().await;
         let idx = guard.as_ref().map_or(0, |(idx, _)| idx.get());
-        *guard = Some((NonZeroU64::new(idx + 1).unwrap(), new_data));
+        let idx = NonZeroU64::new(idx + 1).unwrap();
+        *guard = Some((idx, new_data));
         drop(guard);
         self.cv.notify_all();
+        idx
+    }
+}
+
+struct Subscription<'a>(&'a AtomicUsize);
+
+impl<'a> Subscription<'a> {
+    fn new(subscribers: &'a AtomicUsize) -> Self {
+        let _ = subscribers.fetch_add(1, Ordering::Relaxed);
+        Self(subscribers)
+    }
+}
+
+impl Drop for Subscription<'_> {
+    fn drop(&mut self) {
+        let _ = self.0.fetch_sub(1, Ordering::Relaxed);
     }
 }
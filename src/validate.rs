@@ -0,0 +1,33 @@
+use crate::filter::{FilterFrame, FrameFilter, Verdict};
+
+/// Drops parts that are not JPEG images, or that were truncated.
+///
+/// The check is cheap, the image is not decoded.
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Validate {
+    /// Drop parts without a `Content-Type` header, too
+    #[serde(default)]
+    require_content_type: bool,
+}
+
+impl FrameFilter for Validate {
+    fn name(&self) -> &'static str {
+        "validate"
+    }
+
+    fn apply(&self, frame: &mut FilterFrame) -> anyhow::Result<Verdict> {
+        match frame.headers.get(http::header::CONTENT_TYPE) {
+            Some(value) if value.as_bytes() != b"image/jpeg" => return Ok(Verdict::Drop),
+            None if self.require_content_type => return Ok(Verdict::Drop),
+            _ => {},
+        }
+        // a previous filter may already have decoded the image
+        if let Some(jpeg) = frame.jpeg() {
+            if !jpeg.starts_with(b"\xff\xd8") || !jpeg.ends_with(b"\xff\xd9") {
+                return Ok(Verdict::Drop);
+            }
+        }
+        Ok(Verdict::Keep)
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroU64;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::ImageFormat;
+use tokio::task::spawn_blocking;
+
+use crate::frame::Frame;
+
+/// How to scale and re-encode an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Profile {
+    /// Maximum width of the image, the aspect ratio is retained
+    pub width: Option<u32>,
+    /// Maximum height of the image, the aspect ratio is retained
+    pub height: Option<u32>,
+    /// JPEG quality, 1 ..= 100
+    pub quality: u8,
+}
+
+impl Profile {
+    pub const DEFAULT_QUALITY: u8 = 80;
+    /// The profile named `thumbnail`, which the index page uses, unless it is given explicitly.
+    pub const THUMBNAIL: Self = Self {
+        width: Some(320),
+        height: None,
+        quality: Self::DEFAULT_QUALITY,
+    };
+
+    pub fn new(
+        width: Option<u32>,
+        height: Option<u32>,
+        quality: Option<u8>,
+    ) -> Result<Self, String> {
+        if width == Some(0) || height == Some(0) {
+            return Err("Width and height must not be zero".to_owned());
+        }
+        let quality = quality.unwrap_or(Self::DEFAULT_QUALITY);
+        if !(1..=100).contains(&quality) {
+            return Err(format!("Quality must be in 1 ..= 100, not {quality}"));
+        }
+        Ok(Self {
+            width,
+            height,
+            quality,
+        })
+    }
+}
+
+/// A named profile, e.g. `mobile:width=320,quality=60`.
+#[derive(Debug, Clone)]
+pub struct NamedProfile {
+    pub name: String,
+    pub profile: Profile,
+}
+
+impl FromStr for NamedProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, spec) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Expected NAME:SPEC, got {s:?}"))?;
+        let (mut width, mut height, mut quality) = (None, None, None);
+        for setting in spec.split(',').filter(|s| !s.is_empty()) {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("Expected KEY=VALUE, got {setting:?}"))?;
+            let err = |err: &dyn fmt::Display| format!("Bad value for {key:?}: {err}");
+            match key {
+                "width" => width = Some(value.parse().map_err(|e| err(&e))?),
+                "height" => height = Some(value.parse().map_err(|e| err(&e))?),
+                "quality" => quality = Some(value.parse().map_err(|e| err(&e))?),
+                _ => return Err(format!("Unknown setting {key:?}")),
+            }
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            profile: Profile::new(width, height, quality)?,
+        })
+    }
+}
+
+type Slot = Arc<tokio::sync::Mutex<Option<(NonZeroU64, Frame)>>>;
+
+/// Re-encoded variants of the latest image, shared by all clients that use the same [`Profile`].
+#[derive(Debug, Default)]
+pub struct Variants {
+    entries: Mutex<HashMap<Profile, Entry>>,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    slot: Slot,
+    /// Number of [`Lease`]s of the profile
+    leases: usize,
+    /// Whether the profile was given with `--profile`, and does not count towards the limit
+    named: bool,
+}
+
+impl Variants {
+    /// Reserves a variant for a client, until the lease is dropped.
+    ///
+    /// Profiles that are not `named` are refused if `limit` of them are already in use. A profile
+    /// is in use while it is leased, and until the next image is published.
+    pub fn lease(&self, profile: Profile, named: bool, limit: usize) -> Option<Lease<'_>> {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(&profile)
+            && !named
+            && entries.values().filter(|entry| !entry.named).count() >= limit
+        {
+            return None;
+        }
+        let entry = entries.entry(profile).or_insert_with(|| Entry {
+            named,
+            ..Entry::default()
+        });
+        entry.leases += 1;
+        Some(Lease {
+            variants: self,
+            profile,
+            slot: Arc::clone(&entry.slot),
+        })
+    }
+
+    /// Forgets the variants that are not leased, called when a new image was published.
+    pub fn forget_unused(&self) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|_, entry| entry.leases > 0);
+    }
+}
+
+/// A [`Profile`] that a client uses, see [`Variants::lease()`].
+#[derive(Debug)]
+pub struct Lease<'a> {
+    variants: &'a Variants,
+    profile: Profile,
+    slot: Slot,
+}
+
+impl Lease<'_> {
+    /// Returns `frame` with the sequence number `seq` transcoded according to the profile.
+    ///
+    /// The transcoding is done at most once per profile and image.
+    pub async fn get(&self, seq: NonZeroU64, frame: &Frame) -> Result<Frame, Error> {
+        let mut guard = self.slot.lock().await;
+        if let Some((idx, ref variant)) = *guard {
+            if idx >= seq {
+                return Ok(variant.clone());
+            }
+        }
+        let variant = transcode(self.profile, frame.clone()).await?;
+        *guard = Some((seq, variant.clone()));
+        Ok(variant)
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        let mut entries = self.variants.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(&self.profile) {
+            entry.leases -= 1;
+        }
+    }
+}
+
+async fn transcode(profile: Profile, frame: Frame) -> Result<Frame, Error> {
+    spawn_blocking(move || {
+        let mut image = image::load_from_memory_with_format(&frame.body, ImageFormat::Jpeg)
+            .map_err(Error::Decode)?;
+        let width = profile.width.unwrap_or(u32::MAX).min(image.width());
+        let height = profile.height.unwrap_or(u32::MAX).min(image.height());
+        if (width, height) != (image.width(), image.height()) {
+            image = image.resize(width, height, FilterType::Triangle);
+        }
+
+        let mut data = Vec::new();
+        JpegEncoder::new_with_quality(&mut data, profile.quality)
+            .encode_image(&image)
+            .map_err(Error::Encode)?;
+        let placeholder = frame.placeholder;
+        Ok(Frame {
+            placeholder,
+            ..Frame::new(&data, frame.received, frame.tags)
+        })
+    })
+    .await
+    .map_err(Error::JoinBlocking)?
+}
+
+#[derive(thiserror::Error, pretty_error_debug::Debug)]
+pub enum Error {
+    #[error("Could not start blocking thread")]
+    JoinBlocking(#[source] tokio::task::JoinError),
+    #[error("Could not decode image")]
+    Decode(#[source] image::ImageError),
+    #[error("Could not encode image")]
+    Encode(#[source] image::ImageError),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use http::HeaderMap;
+    use image::{GenericImageView, RgbImage};
+
+    use super::*;
+
+    fn width(width: u32) -> Profile {
+        Profile::new(Some(width), None, None).unwrap()
+    }
+
+    fn frame() -> Frame {
+        let image = RgbImage::from_fn(64, 48, |x, y| image::Rgb([x as u8 * 4, y as u8 * 5, 0]));
+        let mut data = Vec::new();
+        JpegEncoder::new(&mut data).encode_image(&image).unwrap();
+        Frame::new(&data, SystemTime::now(), HeaderMap::new())
+    }
+
+    #[test]
+    fn profiles() {
+        assert_eq!(
+            Profile::new(Some(320), None, None),
+            Ok(Profile {
+                width: Some(320),
+                height: None,
+                quality: Profile::DEFAULT_QUALITY,
+            }),
+        );
+        assert!(Profile::new(Some(0), None, None).is_err());
+        assert!(Profile::new(None, Some(0), None).is_err());
+        assert!(Profile::new(None, None, Some(0)).is_err());
+        assert!(Profile::new(None, None, Some(101)).is_err());
+        assert_eq!(Profile::new(Some(320), None, None), Ok(Profile::THUMBNAIL));
+
+        let named: NamedProfile = "mobile:width=320,height=240,quality=60".parse().unwrap();
+        assert_eq!(named.name, "mobile");
+        assert_eq!(
+            named.profile,
+            Profile::new(Some(320), Some(240), Some(60)).unwrap()
+        );
+        let named: NamedProfile = "original:".parse().unwrap();
+        assert_eq!(named.profile, Profile::new(None, None, None).unwrap());
+        assert!("mobile".parse::<NamedProfile>().is_err());
+        assert!("mobile:width".parse::<NamedProfile>().is_err());
+        assert!("mobile:width=wide".parse::<NamedProfile>().is_err());
+        assert!("mobile:depth=8".parse::<NamedProfile>().is_err());
+        assert!("mobile:quality=0".parse::<NamedProfile>().is_err());
+    }
+
+    #[test]
+    fn limit() {
+        let variants = Variants::default();
+        let first = variants.lease(width(1), false, 2).unwrap();
+        let second = variants.lease(width(2), false, 2).unwrap();
+        assert!(variants.lease(width(3), false, 2).is_none());
+        // profiles in use and named profiles are always admitted
+        let again = variants.lease(width(1), false, 2).unwrap();
+        let named = variants.lease(width(4), true, 2).unwrap();
+        assert!(variants.lease(width(5), true, 0).is_some());
+
+        drop((first, again, named));
+        // until the next image is published, the profile counts as in use
+        assert!(variants.lease(width(3), false, 2).is_none());
+        variants.forget_unused();
+        let third = variants.lease(width(3), false, 2).unwrap();
+        // still leased
+        variants.forget_unused();
+        assert!(variants.lease(width(6), false, 2).is_none());
+        drop((second, third));
+    }
+
+    #[test]
+    fn concurrent_leases() {
+        let variants = Variants::default();
+        let leases = std::thread::scope(|scope| {
+            let threads = (1..=16)
+                .map(|idx| {
+                    let variants = &variants;
+                    scope.spawn(move || variants.lease(width(idx), false, 4))
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(leases.iter().flatten().count(), 4);
+    }
+
+    #[tokio::test]
+    async fn transcoding() {
+        let variants = Variants::default();
+        let frame = frame();
+        let seq = NonZeroU64::new(1).unwrap();
+
+        let lease = variants.lease(width(32), false, 1).unwrap();
+        let variant = lease.get(seq, &frame).await.unwrap();
+        let image = image::load_from_memory(&variant.body).unwrap();
+        assert_eq!(image.dimensions(), (32, 24));
+
+        // shared by all clients of the profile
+        let other = variants.lease(width(32), false, 1).unwrap();
+        let again = other.get(seq, &frame).await.unwrap();
+        assert_eq!(again.body.as_ptr(), variant.body.as_ptr());
+
+        let next = lease
+            .get(NonZeroU64::new(2).unwrap(), &frame)
+            .await
+            .unwrap();
+        assert_ne!(next.body.as_ptr(), variant.body.as_ptr());
+
+        // images are not scaled up
+        let lease = variants.lease(width(100), true, 0).unwrap();
+        let variant = lease.get(seq, &frame).await.unwrap();
+        let image = image::load_from_memory(&variant.body).unwrap();
+        assert_eq!(image.dimensions(), (64, 48));
+    }
+}
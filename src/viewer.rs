@@ -0,0 +1,54 @@
+//! The HTML pages, compiled into the binary.
+
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{get, HttpResponse};
+use mime::Mime;
+
+use crate::auth::{Denied, User};
+
+/// Lists all streams with live thumbnails.
+#[get("/")]
+pub async fn index() -> HttpResponse {
+    asset(mime::TEXT_HTML_UTF_8, include_str!("assets/index.html"))
+}
+
+/// Shows a single stream in full screen.
+#[get("/view/{name}")]
+pub async fn view() -> HttpResponse {
+    asset(mime::TEXT_HTML_UTF_8, include_str!("assets/view.html"))
+}
+
+/// Shows all streams in a grid, the number of columns can be given with `?columns=`.
+#[get("/wall")]
+pub async fn wall() -> HttpResponse {
+    asset(mime::TEXT_HTML_UTF_8, include_str!("assets/wall.html"))
+}
+
+/// Makes the browser ask for credentials, so that restricted streams are listed, too.
+#[get("/login")]
+pub async fn login(user: User) -> Result<HttpResponse, Denied> {
+    user.require()?;
+    Ok(HttpResponse::SeeOther()
+        .append_header((http::header::LOCATION, "/"))
+        .finish())
+}
+
+#[get("/assets/app.js")]
+pub async fn script() -> HttpResponse {
+    asset(
+        mime::APPLICATION_JAVASCRIPT_UTF_8,
+        include_str!("assets/app.js"),
+    )
+}
+
+#[get("/assets/style.css")]
+pub async fn style() -> HttpResponse {
+    asset(mime::TEXT_CSS_UTF_8, include_str!("assets/style.css"))
+}
+
+fn asset(content_type: Mime, content: &'static str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .append_header(CacheControl(vec![CacheDirective::NoCache]))
+        .body(content)
+}
@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+
+use image::imageops::FilterType;
+use image::{Rgba, RgbaImage};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+use crate::filter::{FilterConfig, FilterFrame, FrameFilter, Verdict};
+use crate::overlay::{blend, Position};
+
+/// A logo drawn into a corner of the image.
+#[derive(clap::Args, Debug, Clone)]
+#[group(id = "watermark")]
+pub struct Args {
+    /// PNG image to draw into the image
+    #[arg(long = "watermark", value_name = "PATH")]
+    path: Option<PathBuf>,
+    /// Corner of the image to draw the watermark into
+    #[arg(long, value_enum, default_value_t = Position::BottomRight)]
+    watermark_position: Position,
+    /// Scaling factor of the watermark
+    #[arg(long, default_value_t = 1.0, value_parser = parse_scale)]
+    watermark_scale: f32,
+    /// Opacity of the watermark, 0.0 ..= 1.0
+    #[arg(long, default_value_t = 1.0, value_parser = parse_opacity)]
+    watermark_opacity: f32,
+}
+
+impl Args {
+    pub fn filter(self) -> Option<FilterConfig> {
+        Some(FilterConfig::Watermark(Config {
+            path: self.path?,
+            position: self.watermark_position,
+            scale: self.watermark_scale,
+            opacity: self.watermark_opacity,
+        }))
+    }
+}
+
+/// A logo drawn into a corner of the image, see [`Args`].
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    path: PathBuf,
+    #[serde(default = "default_position")]
+    position: Position,
+    #[serde(default = "default_one", deserialize_with = "deserialize_scale")]
+    scale: f32,
+    #[serde(default = "default_one", deserialize_with = "deserialize_opacity")]
+    opacity: f32,
+}
+
+/// The decoded, scaled watermark.
+#[derive(Debug)]
+pub struct Watermark {
+    logo: RgbaImage,
+    position: Position,
+}
+
+impl Watermark {
+    /// Reads and prepares the logo. Blocks.
+    pub fn load(config: Config) -> Result<Self, Error> {
+        let Config {
+            path,
+            position,
+            scale,
+            opacity,
+        } = config;
+
+        let logo = image::open(&path)
+            .map_err(|err| Error::Load(path, err))?
+            .into_rgba8();
+        let width = (logo.width() as f32 * scale).round().max(1.0) as u32;
+        let height = (logo.height() as f32 * scale).round().max(1.0) as u32;
+        let mut logo = if (width, height) != logo.dimensions() {
+            image::imageops::resize(&logo, width, height, FilterType::Lanczos3)
+        } else {
+            logo
+        };
+        for Rgba([.., alpha]) in logo.pixels_mut() {
+            *alpha = (f32::from(*alpha) * opacity).round() as u8;
+        }
+        Ok(Self { logo, position })
+    }
+}
+
+impl FrameFilter for Watermark {
+    fn name(&self) -> &'static str {
+        "watermark"
+    }
+
+    fn apply(&self, frame: &mut FilterFrame) -> anyhow::Result<Verdict> {
+        let image = frame.image()?;
+        let (left, top) = self
+            .position
+            .place(image.dimensions(), self.logo.dimensions(), 8);
+        for (x, y, &pixel) in self.logo.enumerate_pixels() {
+            if let Some(dst) = image.get_pixel_mut_checked(left + x, top + y) {
+                blend(dst, pixel);
+            }
+        }
+        Ok(Verdict::Keep)
+    }
+}
+
+fn parse_scale(scale: &str) -> Result<f32, String> {
+    check_scale(scale.parse().map_err(|err| format!("{err}"))?)
+}
+
+fn parse_opacity(opacity: &str) -> Result<f32, String> {
+    check_opacity(opacity.parse().map_err(|err| format!("{err}"))?)
+}
+
+fn check_scale(scale: f32) -> Result<f32, String> {
+    match scale > 0.0 && scale.is_finite() {
+        true => Ok(scale),
+        false => Err(format!("Expected a positive number, got {scale}")),
+    }
+}
+
+fn check_opacity(opacity: f32) -> Result<f32, String> {
+    match (0.0..=1.0).contains(&opacity) {
+        true => Ok(opacity),
+        false => Err(format!("Expected a number in 0.0 ..= 1.0, got {opacity}")),
+    }
+}
+
+fn deserialize_scale<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    check_scale(f32::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+fn deserialize_opacity<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    check_opacity(f32::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+fn default_position() -> Position {
+    Position::BottomRight
+}
+
+fn default_one() -> f32 {
+    1.0
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not load watermark {0:?}")]
+    Load(PathBuf, #[source] image::ImageError),
+}